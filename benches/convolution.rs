@@ -0,0 +1,85 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use procon2022_comp_2nd::{
+    audio_vec::owned::{
+        mod_int::ModInt998244353,
+        ntt::Ntt,
+        pixel::{Pixel, PixelModuli},
+        Owned,
+    },
+    precalc::{load_card_voices, Precalculation},
+    request::{mock::MockRequester, Requester},
+    solve::{card_voice::CardLibrary, Loss},
+};
+
+/// 読み上げ音声と同じ 16 ビットの範囲の値を `len` 個並べたもの.
+fn samples(len: usize, seed: u64) -> Owned {
+    Owned::from_pixels((0..len as u64).map(|i| {
+        Pixel::from_signed(((i ^ seed).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 48) as i64 - 32768)
+    }))
+}
+
+fn ntt(c: &mut Criterion) {
+    let ntt = Ntt::<998244353>::new();
+    let mut group = c.benchmark_group("ntt");
+    for level in [10, 14, 17, 20] {
+        let len = 1 << level;
+        let input: Vec<_> = (0..len as u64).map(ModInt998244353::new).collect();
+        // 前計算は読み札どうしで使い回すので, 測る前に作っておく
+        ntt.plan(len);
+        group.bench_function(BenchmarkId::new("transform", len), |b| {
+            b.iter_batched_ref(
+                || input.clone(),
+                |vec| ntt.transform(vec),
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_function(BenchmarkId::new("inverse_transform", len), |b| {
+            b.iter_batched_ref(
+                || input.clone(),
+                |vec| ntt.inverse_transform(vec),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// 短い方の長さごとに, 直接計算と数論変換を比べて [`Owned::NAIVE_CONVOLUTION_THRESHOLD`] を決める.
+fn convolution(c: &mut Criterion) {
+    let ntts = Default::default();
+    let long = samples(48_000, 1);
+    let mut group = c.benchmark_group("convolution");
+    for short_len in [8, 16, 32, 40, 64, 128] {
+        let short = samples(short_len, 2);
+        group.bench_function(BenchmarkId::new("naive", short_len), |b| {
+            b.iter(|| long.convolution_with_threshold::<PixelModuli>(&short, &ntts, usize::MAX))
+        });
+        group.bench_function(BenchmarkId::new("ntt", short_len), |b| {
+            b.iter(|| long.convolution_with_threshold::<PixelModuli>(&short, &ntts, 0))
+        });
+    }
+    group.finish();
+}
+
+fn pipeline(c: &mut Criterion) {
+    let library = CardLibrary::from_env().unwrap();
+    let card_voices = load_card_voices(&library).unwrap();
+    let card = library.find("E01").unwrap();
+    let requester = MockRequester::new(["assets", "sample", "sample_Q_E01"].into_iter().collect());
+    let problem = requester.get_problem_voice(1).unwrap();
+
+    c.bench_function("precalculation", |b| {
+        b.iter(|| Precalculation::new(&card_voices))
+    });
+
+    let loss = Loss::new(library, card_voices);
+    c.bench_function("evaluate", |b| b.iter(|| loss.evaluate(&problem, card)));
+
+    let mut group = c.benchmark_group("find_points");
+    group.sample_size(10);
+    group.bench_function("sample_Q_E01", |b| b.iter(|| loss.find_points(&problem)));
+    group.finish();
+}
+
+criterion_group!(benches, ntt, convolution, pipeline);
+criterion_main!(benches);
@@ -0,0 +1,94 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use procon2022_comp_2nd::audio_vec::owned::{mod_int::ModInt998244353, ntt::Ntt};
+
+const LEN: usize = 1 << 16;
+
+fn values(seed: u64) -> Vec<ModInt998244353> {
+    (0..LEN as u64)
+        .map(|i| ModInt998244353::new((i ^ seed).wrapping_mul(0x9E37_79B9_7F4A_7C15)))
+        .collect()
+}
+
+/// 1 要素ずつの演算と, まとめて計算する演算を比べる.
+fn batch(c: &mut Criterion) {
+    let lhs = values(1);
+    let rhs = values(2);
+
+    let mut group = c.benchmark_group("mul_assign");
+    group.bench_function(BenchmarkId::new("scalar", LEN), |b| {
+        b.iter_batched_ref(
+            || lhs.clone(),
+            |lhs| {
+                for (l, &r) in lhs.iter_mut().zip(&rhs) {
+                    *l *= r;
+                }
+            },
+            criterion::BatchSize::LargeInput,
+        )
+    });
+    group.bench_function(BenchmarkId::new("slice", LEN), |b| {
+        b.iter_batched_ref(
+            || lhs.clone(),
+            |lhs| ModInt998244353::mul_assign_slice(lhs, &rhs),
+            criterion::BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+
+    let quarter = LEN / 4;
+    let twiddles = [
+        &rhs[..quarter],
+        &rhs[quarter..2 * quarter],
+        &rhs[2 * quarter..3 * quarter],
+    ];
+    let imag = rhs[3 * quarter];
+    let mut group = c.benchmark_group("butterfly4");
+    group.bench_function(BenchmarkId::new("scalar", quarter), |b| {
+        b.iter_batched_ref(
+            || lhs.clone(),
+            |vec| {
+                let [t1, t2, t3] = twiddles;
+                let (half0, half1) = vec.split_at_mut(2 * quarter);
+                let (a0, a1) = half0.split_at_mut(quarter);
+                let (a2, a3) = half1.split_at_mut(quarter);
+                for j in 0..quarter {
+                    let c1 = a1[j] * t1[j];
+                    let c2 = a2[j] * t2[j];
+                    let c3 = a3[j] * t3[j];
+                    let (s0, d0) = (a0[j] + c1, a0[j] - c1);
+                    let (s1, d1) = (c2 + c3, (c2 - c3) * imag);
+                    a0[j] = s0 + s1;
+                    a1[j] = d0 + d1;
+                    a2[j] = s0 - s1;
+                    a3[j] = d0 - d1;
+                }
+            },
+            criterion::BatchSize::LargeInput,
+        )
+    });
+    group.bench_function(BenchmarkId::new("slices", quarter), |b| {
+        b.iter_batched_ref(
+            || lhs.clone(),
+            |vec| {
+                let (half0, half1) = vec.split_at_mut(2 * quarter);
+                let (a0, a1) = half0.split_at_mut(quarter);
+                let (a2, a3) = half1.split_at_mut(quarter);
+                ModInt998244353::butterfly4_slices([a0, a1, a2, a3], twiddles, imag);
+            },
+            criterion::BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+
+    let ntt = Ntt::<998244353>::new();
+    c.bench_function("ntt_transform", |b| {
+        b.iter_batched_ref(
+            || lhs.clone(),
+            |vec| ntt.transform(vec),
+            criterion::BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group!(benches, batch);
+criterion_main!(benches);
@@ -0,0 +1,53 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use procon2022_comp_2nd::{
+    audio_vec::{owned::Owned, AudioVec},
+    precalc::load_card_voices,
+    request::{mock::MockRequester, Requester},
+    solve::{card_voice::CardLibrary, InspectPoint, Loss},
+};
+
+/// 問題 `sample_Q_E01` と, その正解の解.
+fn sample() -> (Loss, Owned, Vec<InspectPoint>) {
+    let library = CardLibrary::from_env().unwrap();
+    let card_voices = load_card_voices(&library).unwrap();
+    let answer = ["E01", "E02", "E03"]
+        .into_iter()
+        .map(|label| InspectPoint {
+            using_voice: library.find(label).unwrap(),
+            delay: 0,
+            score: 0,
+        })
+        .collect();
+    let loss = Loss::new(library, card_voices);
+    let requester = MockRequester::new(["assets", "sample", "sample_Q_E01"].into_iter().collect());
+    let problem = requester.get_problem_voice(1).unwrap();
+    (loss, problem, answer)
+}
+
+fn validate(c: &mut Criterion) {
+    let (loss, problem, answer) = sample();
+    let len = problem.len();
+
+    c.bench_function("validate", |b| b.iter(|| loss.validate(&problem, &answer)));
+    c.bench_function("residual", |b| b.iter(|| loss.residual(&problem, &answer)));
+
+    // 読み札を遅らせて重ね, 問題から引く組み合わせを, 要素ごとに get で辿るか fill でまとめて求めるか
+    let cards: Vec<_> = answer
+        .iter()
+        .map(|point| loss.delayed_card(point, len))
+        .collect();
+    let residual = (&problem).sub(
+        (&cards[0])
+            .delay(-100)
+            .add((&cards[1]).delay(200))
+            .add((&cards[2]).flip().delay(1 - len as isize))
+            .clip(len),
+    );
+    c.bench_function("combinators_by_get", |b| {
+        b.iter(|| Owned::from_pixels((0..len as isize).map(|i| residual.get(i))))
+    });
+    c.bench_function("combinators_by_fill", |b| b.iter(|| residual.to_owned(len)));
+}
+
+criterion_group!(benches, validate);
+criterion_main!(benches);
@@ -1,5 +1,3 @@
-use cast::isize;
-
 use self::owned::{pixel::Pixel, Owned};
 
 pub mod composite;
@@ -8,6 +6,16 @@ pub mod owned;
 pub trait AudioVec {
     fn get(&self, index: isize) -> Pixel;
 
+    /// 添字 `start` からの `out.len()` 個を `out` に書き込む.
+    ///
+    /// 既定では 1 つずつ [`AudioVec::get`] を呼ぶ. まとめて計算できる組み合わせはこれを上書きし,
+    /// 深く組み合わせても要素ごとに各段を辿り直さずに済むようにする.
+    fn fill(&self, start: isize, out: &mut [Pixel]) {
+        for (i, px) in out.iter_mut().enumerate() {
+            *px = self.get(start + i as isize);
+        }
+    }
+
     fn add<B>(self, other: B) -> composite::Add<Self, B>
     where
         Self: Sized,
@@ -44,14 +52,83 @@ pub trait AudioVec {
         composite::Flipped { vec: self }
     }
 
-    fn clip(self, len: usize) -> composite::Clipped<Self>
+    fn scale(self, gain: i64) -> composite::Scaled<Self>
+    where
+        Self: Sized,
+    {
+        composite::Scaled {
+            vec: self,
+            gain: Pixel::from_signed(gain),
+        }
+    }
+
+    /// `start` から `len` 個だけを切り出し, 添字 0 から始まるようにする. 範囲の外は 0 になる.
+    fn window(self, start: isize, len: usize) -> composite::Windowed<Self>
+    where
+        Self: Sized,
+    {
+        composite::Windowed {
+            vec: self,
+            start,
+            len,
+        }
+    }
+
+    /// 各要素に `f` を適用する.
+    fn map<F>(self, f: F) -> composite::Mapped<Self, F>
+    where
+        Self: Sized,
+        F: Fn(Pixel) -> Pixel,
+    {
+        composite::Mapped { vec: self, f }
+    }
+
+    /// 添字 `len` 以降を `other` に置き換えて後ろにつなげる. 添字 `len` が `other` の添字 0 に当たる.
+    fn concat<B>(self, len: usize, other: B) -> composite::Concat<Self, B>
+    where
+        Self: Sized,
+        B: AudioVec,
+    {
+        composite::Concat {
+            left: self,
+            left_len: len,
+            right: other,
+        }
+    }
+
+    /// `factor` 個ずつまとめて間引く. 添字 `i` は元の `i * factor` からの `factor` 個に当たる.
+    ///
+    /// `factor` は 1 以上であること. 0 なら panic する.
+    fn decimate(self, factor: usize) -> composite::Decimated<Self>
     where
         Self: Sized,
     {
-        composite::Clipped { vec: self, len }
+        assert!(0 < factor, "decimation factor must be positive");
+        composite::Decimated { vec: self, factor }
+    }
+
+    /// `0..len` の範囲を切り出し, 16 ビットの範囲に収める.
+    fn clip(self, len: usize) -> composite::Mapped<composite::Windowed<Self>, fn(Pixel) -> Pixel>
+    where
+        Self: Sized,
+    {
+        self.window(0, len).map(composite::saturate)
     }
 
     fn to_owned(&self, len: usize) -> Owned {
-        Owned::from_pixels((0..len).map(|index| self.get(isize(index).unwrap())))
+        let mut pixels = vec![Pixel::default(); len];
+        self.fill(0, &mut pixels);
+        Owned::from_pixels(pixels)
+    }
+}
+
+/// 参照からも組み合わせられるようにして, 元の音声を複製せずに済ませる.
+impl<T: AudioVec + ?Sized> AudioVec for &T {
+    fn get(&self, index: isize) -> Pixel {
+        (**self).get(index)
+    }
+
+    fn fill(&self, start: isize, out: &mut [Pixel]) {
+        (**self).fill(start, out);
     }
 }
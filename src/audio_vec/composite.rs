@@ -1,6 +1,9 @@
 use cast::usize;
 
-use super::{owned::pixel::Pixel, AudioVec};
+use super::{
+    owned::{overlap, pixel::Pixel, Owned},
+    AudioVec,
+};
 
 #[derive(Debug)]
 pub struct Add<A, B> {
@@ -12,6 +15,15 @@ impl<A: AudioVec, B: AudioVec> AudioVec for Add<A, B> {
     fn get(&self, index: isize) -> Pixel {
         self.left.get(index) + self.right.get(index)
     }
+
+    fn fill(&self, start: isize, out: &mut [Pixel]) {
+        self.left.fill(start, out);
+        let mut right = vec![Pixel::default(); out.len()];
+        self.right.fill(start, &mut right);
+        for (px, right) in out.iter_mut().zip(right) {
+            *px += right;
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -24,6 +36,15 @@ impl<A: AudioVec, B: AudioVec> AudioVec for Sub<A, B> {
     fn get(&self, index: isize) -> Pixel {
         self.left.get(index) - self.right.get(index)
     }
+
+    fn fill(&self, start: isize, out: &mut [Pixel]) {
+        self.left.fill(start, out);
+        let mut right = vec![Pixel::default(); out.len()];
+        self.right.fill(start, &mut right);
+        for (px, right) in out.iter_mut().zip(right) {
+            *px = *px - right;
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -37,6 +58,10 @@ impl<T: AudioVec> AudioVec for Delayed<T> {
     fn get(&self, index: isize) -> Pixel {
         self.vec.get(index + self.delay)
     }
+
+    fn fill(&self, start: isize, out: &mut [Pixel]) {
+        self.vec.fill(start + self.delay, out);
+    }
 }
 
 #[derive(Debug)]
@@ -48,21 +73,270 @@ impl<T: AudioVec> AudioVec for Flipped<T> {
     fn get(&self, index: isize) -> Pixel {
         self.vec.get(-index)
     }
+
+    fn fill(&self, start: isize, out: &mut [Pixel]) {
+        // 添字 start..start + n は元の -(start + n - 1)..=-start に当たる
+        self.vec.fill(-(start + out.len() as isize - 1), out);
+        out.reverse();
+    }
+}
+
+#[derive(Debug)]
+pub struct Scaled<T> {
+    pub(super) vec: T,
+    pub(super) gain: Pixel,
+}
+
+impl<T: AudioVec> AudioVec for Scaled<T> {
+    fn get(&self, index: isize) -> Pixel {
+        self.vec.get(index) * self.gain
+    }
+
+    fn fill(&self, start: isize, out: &mut [Pixel]) {
+        self.vec.fill(start, out);
+        for px in out {
+            *px = *px * self.gain;
+        }
+    }
 }
 
 #[derive(Debug)]
-pub struct Clipped<T> {
+pub struct Windowed<T> {
     pub(super) vec: T,
+    pub(super) start: isize,
     pub(super) len: usize,
 }
 
-impl<T: AudioVec> AudioVec for Clipped<T> {
+impl<T: AudioVec> AudioVec for Windowed<T> {
     fn get(&self, index: isize) -> Pixel {
-        if !usize(index).map_or(false, |index| (0..self.len).contains(&index)) {
+        if !usize(index).is_ok_and(|index| index < self.len) {
             return Default::default();
         }
-        let min = i16::MIN as i64;
-        let max = i16::MAX as i64;
-        self.vec.get(index).clamp(min, max)
+        self.vec.get(self.start + index)
+    }
+
+    fn fill(&self, start: isize, out: &mut [Pixel]) {
+        out.fill(Pixel::default());
+        let (lo, hi) = overlap(start, out.len(), 0, self.len);
+        if lo < hi {
+            self.vec.fill(
+                self.start + lo,
+                &mut out[(lo - start) as usize..(hi - start) as usize],
+            );
+        }
     }
 }
+
+/// 開始位置の分かっている分割データを並べて 1 つの音声にしたもの. 分割データの無いところは 0 になる.
+#[derive(Debug, Default)]
+pub struct Stitched {
+    /// 開始位置の順に並べた, 開始位置と分割データ
+    parts: Vec<(usize, Owned)>,
+}
+
+impl Stitched {
+    /// それぞれの開始位置 `offsets` に分割データ `chunks` を置く. 重なるところは後のものを使う.
+    pub fn new(offsets: impl IntoIterator<Item = usize>, chunks: Vec<Owned>) -> Self {
+        let mut parts: Vec<_> = offsets.into_iter().zip(chunks).collect();
+        parts.sort_by_key(|&(start, _)| start);
+        Self { parts }
+    }
+
+    /// 分割データを隙間なく順につなげる.
+    pub fn contiguous(chunks: Vec<Owned>) -> Self {
+        let offsets: Vec<_> = chunks
+            .iter()
+            .scan(0, |start, chunk| {
+                let offset = *start;
+                *start += chunk.len();
+                Some(offset)
+            })
+            .collect();
+        Self::new(offsets, chunks)
+    }
+
+    /// 最後の分割データの終わりまでの長さ.
+    pub fn len(&self) -> usize {
+        self.parts
+            .iter()
+            .map(|(start, chunk)| start + chunk.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl AudioVec for Stitched {
+    fn get(&self, index: isize) -> Pixel {
+        let Ok(index) = usize(index) else {
+            return Default::default();
+        };
+        // 後ろから探して, `index` を含む最後の分割データを使う
+        let candidates = self.parts.partition_point(|&(start, _)| start <= index);
+        self.parts[..candidates]
+            .iter()
+            .rev()
+            .find(|(start, chunk)| index < start + chunk.len())
+            .map_or_else(Default::default, |(start, chunk)| {
+                chunk.get((index - start) as isize)
+            })
+    }
+
+    fn fill(&self, start: isize, out: &mut [Pixel]) {
+        out.fill(Pixel::default());
+        // 開始位置の順に書き込み, 重なるところは後のもので上書きする
+        for (offset, chunk) in &self.parts {
+            let offset = *offset as isize;
+            let (lo, hi) = overlap(start, out.len(), offset, chunk.len());
+            if lo < hi {
+                chunk.fill(
+                    lo - offset,
+                    &mut out[(lo - start) as usize..(hi - start) as usize],
+                );
+            }
+        }
+    }
+}
+
+/// 16 ビットの範囲に収める.
+pub fn saturate(px: Pixel) -> Pixel {
+    px.clamp(i16::MIN as i64, i16::MAX as i64)
+}
+
+#[derive(Debug)]
+pub struct Mapped<T, F> {
+    pub(super) vec: T,
+    pub(super) f: F,
+}
+
+impl<T: AudioVec, F: Fn(Pixel) -> Pixel> AudioVec for Mapped<T, F> {
+    fn get(&self, index: isize) -> Pixel {
+        (self.f)(self.vec.get(index))
+    }
+
+    fn fill(&self, start: isize, out: &mut [Pixel]) {
+        self.vec.fill(start, out);
+        for px in out {
+            *px = (self.f)(*px);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Concat<A, B> {
+    pub(super) left: A,
+    pub(super) left_len: usize,
+    pub(super) right: B,
+}
+
+impl<A: AudioVec, B: AudioVec> AudioVec for Concat<A, B> {
+    fn get(&self, index: isize) -> Pixel {
+        let left_len = self.left_len as isize;
+        if index < left_len {
+            self.left.get(index)
+        } else {
+            self.right.get(index - left_len)
+        }
+    }
+
+    fn fill(&self, start: isize, out: &mut [Pixel]) {
+        let left_len = self.left_len as isize;
+        let split = (left_len - start).clamp(0, out.len() as isize) as usize;
+        let (left, right) = out.split_at_mut(split);
+        self.left.fill(start, left);
+        self.right.fill(start + split as isize - left_len, right);
+    }
+}
+
+#[derive(Debug)]
+pub struct Decimated<T> {
+    pub(super) vec: T,
+    pub(super) factor: usize,
+}
+
+impl<T: AudioVec> AudioVec for Decimated<T> {
+    /// `factor` 個ずつの和を 1 つの要素にする. 和を取ることで簡単な低域通過フィルタにもなる.
+    fn get(&self, index: isize) -> Pixel {
+        let factor = self.factor as isize;
+        (index * factor..(index + 1) * factor)
+            .map(|i| self.vec.get(i))
+            .sum()
+    }
+
+    fn fill(&self, start: isize, out: &mut [Pixel]) {
+        let mut source = vec![Pixel::default(); out.len() * self.factor];
+        self.vec.fill(start * self.factor as isize, &mut source);
+        for (px, block) in out.iter_mut().zip(source.chunks_exact(self.factor)) {
+            *px = block.iter().copied().sum();
+        }
+    }
+}
+
+#[cfg(test)]
+fn pcm(vec: &dyn AudioVec, len: usize) -> Vec<i64> {
+    (0..len).map(|i| vec.get(i as isize).as_i64()).collect()
+}
+
+#[test]
+fn combinators() {
+    let a = Owned::from_pcm(&[1, 2, 3, 4, 5, 6]);
+    let b = Owned::from_pcm(&[-1, -2]);
+
+    assert_eq!(pcm(&(&a).scale(-3), 3), [-3, -6, -9]);
+    assert_eq!(pcm(&(&a).window(2, 3), 5), [3, 4, 5, 0, 0]);
+    assert_eq!(pcm(&(&a).window(-1, 3), 3), [0, 1, 2]);
+    assert_eq!(pcm(&(&a).map(|px| px * px), 3), [1, 4, 9]);
+    assert_eq!(pcm(&(&a).decimate(4), 3), [10, 11, 0]);
+    assert_eq!(
+        pcm(&(&b).concat(2, (&a).window(0, 2)), 5),
+        [-1, -2, 1, 2, 0]
+    );
+    assert_eq!(a.get(-1), Pixel::default());
+}
+
+#[test]
+fn stitched() {
+    let a = Owned::from_pcm(&[1, 2, 3, 4, 5, 6]);
+    let b = Owned::from_pcm(&[-1, -2]);
+
+    // 開始位置の順に並べ, 重なるところは後から始まる分割データを使う
+    let stitched = Stitched::new([4, 0], vec![b.clone(), a.clone()]);
+    assert_eq!(pcm(&stitched, 7), [1, 2, 3, 4, -1, -2, 0]);
+    // 分割データの無い範囲は 0
+    let gapped = Stitched::new([3], vec![b.clone()]);
+    assert_eq!(gapped.len(), 5);
+    assert_eq!(pcm(&gapped, 6), [0, 0, 0, -1, -2, 0]);
+}
+
+#[test]
+fn fill_matches_get() {
+    let a = Owned::from_pcm(&[1, -2, 3, -4, 5, -6, 7]);
+    let b = Owned::from_pcm(&[10, 20, 30]);
+    let check = |vec: &dyn AudioVec| {
+        for start in -9..9 {
+            let mut filled = vec![Pixel::default(); 11];
+            vec.fill(start, &mut filled);
+            let expected: Vec<_> = (0..11).map(|i| vec.get(start + i)).collect();
+            assert_eq!(filled, expected, "start {start}");
+        }
+    };
+
+    check(&a);
+    check(&(&a).add((&b).delay(-2)));
+    check(&(&a).sub(&b).flip());
+    check(&(&a).scale(3).window(2, 4));
+    check(&(&a).map(|px| px * px).delay(1));
+    check(&(&b).concat(4, (&a).flip()));
+    check(&(&a).decimate(3).clip(2));
+    check(&Stitched::new([5, 2], vec![b.clone(), a.clone()]));
+}
+
+#[test]
+#[should_panic(expected = "decimation factor must be positive")]
+fn decimate_rejects_zero_factor() {
+    let a = Owned::from_pcm(&[1, 2, 3]);
+    (&a).decimate(0);
+}
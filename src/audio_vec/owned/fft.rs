@@ -0,0 +1,49 @@
+use std::f64::consts::TAU;
+
+use num::complex::Complex64;
+
+use super::ntt::{butterfly, Butterfly};
+
+/// 複素数の高速フーリエ変換. バタフライ演算は [`Ntt`](super::ntt::Ntt) と共有する.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fft {
+    /// `root_of_power_of_2[k]` は 1 の原始 2^k 乗根 `exp(-2πi / 2^k)`.
+    root_of_power_of_2: Vec<Complex64>,
+}
+
+impl Fft {
+    /// 長さ `2^level` までの変換を用意する.
+    pub fn new(level: usize) -> Self {
+        let root_of_power_of_2 = (0..=level)
+            .map(|k| Complex64::from_polar(1.0, -TAU / (1u64 << k) as f64))
+            .collect();
+        Self { root_of_power_of_2 }
+    }
+
+    pub fn transform(&self, vec: &mut [Complex64]) {
+        butterfly(vec, &self.root_of_power_of_2);
+    }
+}
+
+impl Butterfly for Complex64 {}
+
+#[test]
+fn transform_matches_dft() {
+    let input: Vec<_> = (0..16)
+        .map(|i| Complex64::new((i * 7 % 5) as f64 - 2.0, 0.0))
+        .collect();
+    let mut output = input.clone();
+    Fft::new(4).transform(&mut output);
+
+    for (k, &actual) in output.iter().enumerate() {
+        let expected: Complex64 = input
+            .iter()
+            .enumerate()
+            .map(|(t, &x)| x * Complex64::from_polar(1.0, -TAU * (k * t) as f64 / 16.0))
+            .sum();
+        assert!(
+            (actual - expected).norm() < 1e-9,
+            "{k}: {actual} {expected}"
+        );
+    }
+}
@@ -1,6 +1,8 @@
 use num::{traits::Pow, One, Zero};
 use serde::{Deserialize, Serialize};
 
+mod batch;
+
 const R: u64 = 1 << 32;
 
 /// modulo * modulo_inv ≡ -1 (mod R) となる modulo_inv を求める.
@@ -31,6 +33,8 @@ const fn find_r2(modulo: u32) -> u32 {
 
 pub type ModInt924844033 = ModInt<924844033>;
 pub type ModInt998244353 = ModInt<998244353>;
+pub type ModInt469762049 = ModInt<469762049>;
+pub type ModInt167772161 = ModInt<167772161>;
 #[test]
 fn const_test_998244353() {
     assert_eq!(ModInt998244353::N, 0x3B800001);
@@ -67,14 +71,12 @@ impl<const MOD: u32> From<ModInt<MOD>> for u32 {
 impl<const MOD: u32> ModInt<MOD> {
     #[inline]
     pub fn new(n: u64) -> Self {
-        Self(Self::reduce(n * Self::R2 as u64))
+        Self(Self::reduce(n % MOD as u64 * Self::R2 as u64))
     }
 
     #[inline]
-    pub fn from_signed(mut n: i64) -> Self {
-        while n < 0 {
-            n += MOD as i64;
-        }
+    pub fn from_signed(n: i64) -> Self {
+        let n = n.rem_euclid(MOD as i64);
         Self(Self::reduce(n as u64 * Self::R2 as u64))
     }
 
@@ -98,7 +100,7 @@ impl<const MOD: u32> ModInt<MOD> {
     #[inline]
     pub fn reduce(x: u64) -> u32 {
         let modulo = MOD as u64;
-        debug_assert!(x < modulo * R as u64);
+        debug_assert!(x < modulo * R);
 
         let x_n_prime = (x as u32).wrapping_mul(Self::N_PRIME) as u64;
         let mul = (x + x_n_prime * modulo) / R;
@@ -116,7 +118,43 @@ impl<const MOD: u32> ModInt<MOD> {
         //     max     mid     min
         // <-- Less          Greater -->
         let mid = MOD / 2;
-        todo!()
+        let value = self.as_u32();
+        let signed = if mid < value {
+            value as i64 - MOD as i64
+        } else {
+            value as i64
+        };
+        Self::from_signed(signed.clamp(min, max))
+    }
+}
+
+impl<const MOD: u32> ModInt<MOD> {
+    /// まとめて計算するときに一度に扱う要素数. これより短い列は 1 要素ずつ計算される.
+    pub const LANES: usize = batch::LANES;
+
+    /// `lhs[i] *= rhs[i]` をまとめて行う. 長さは揃っていること.
+    #[inline]
+    pub fn mul_assign_slice(lhs: &mut [Self], rhs: &[Self]) {
+        batch::mul_assign(lhs, rhs);
+    }
+
+    /// `vec[i] *= rhs` をまとめて行う.
+    #[inline]
+    pub fn mul_assign_scalar(vec: &mut [Self], rhs: Self) {
+        batch::mul_assign_scalar(vec, rhs);
+    }
+
+    /// 基数 4 のバタフライ演算をまとめて行う. `quarters` は幅 `4w` の窓を 4 等分したもの.
+    ///
+    /// 窓の半分の幅が `w` の段と `2w` の段の基数 2 のバタフライ演算を続けて行うのと同じ結果になる.
+    /// `twiddles` は `ω_{2w}^j`, `ω_{4w}^j`, `ω_{4w}^{3j}` の 3 つで, `imag` は 1 の原始 4 乗根 ω_4.
+    #[inline]
+    pub fn butterfly4_slices(quarters: [&mut [Self]; 4], twiddles: [&[Self]; 3], imag: Self) {
+        if twiddles[0].len() < Self::LANES {
+            batch::butterfly4_scalar(quarters, twiddles, imag);
+        } else {
+            batch::butterfly4(quarters, twiddles, imag);
+        }
     }
 }
 
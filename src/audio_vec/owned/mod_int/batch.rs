@@ -0,0 +1,249 @@
+//! [`ModInt`] の列をまとめて計算する.
+//!
+//! x86_64 で AVX2 が使えれば 8 要素ずつ, そうでなければ 1 要素ずつ計算する. どちらでも結果は一致する.
+
+use std::sync::OnceLock;
+
+use super::ModInt;
+
+/// AVX2 で一度に計算する要素数.
+pub(super) const LANES: usize = 8;
+
+/// AVX2 が使えるか. 検出は最初の 1 回だけ行う.
+#[inline]
+fn avx2_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        #[cfg(target_arch = "x86_64")]
+        {
+            is_x86_feature_detected!("avx2")
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        {
+            false
+        }
+    })
+}
+
+pub fn mul_assign<const MOD: u32>(lhs: &mut [ModInt<MOD>], rhs: &[ModInt<MOD>]) {
+    assert_eq!(lhs.len(), rhs.len());
+    if avx2_available() {
+        #[cfg(target_arch = "x86_64")]
+        // SAFETY: AVX2 が使えることを確かめた
+        return unsafe { avx2::mul_assign(lhs, rhs) };
+    }
+    for (l, &r) in lhs.iter_mut().zip(rhs) {
+        *l *= r;
+    }
+}
+
+pub fn mul_assign_scalar<const MOD: u32>(vec: &mut [ModInt<MOD>], rhs: ModInt<MOD>) {
+    if avx2_available() {
+        #[cfg(target_arch = "x86_64")]
+        // SAFETY: AVX2 が使えることを確かめた
+        return unsafe { avx2::mul_assign_scalar(vec, rhs) };
+    }
+    for elem in vec {
+        *elem *= rhs;
+    }
+}
+
+/// 基数 4 のバタフライ演算. 引数は [`ModInt::butterfly4_slices`] と同じ.
+pub fn butterfly4<const MOD: u32>(
+    quarters: [&mut [ModInt<MOD>]; 4],
+    twiddles: [&[ModInt<MOD>]; 3],
+    imag: ModInt<MOD>,
+) {
+    let len = twiddles[0].len();
+    assert!(quarters.iter().all(|quarter| quarter.len() == len));
+    assert!(twiddles.iter().all(|twiddle| twiddle.len() == len));
+    if avx2_available() {
+        #[cfg(target_arch = "x86_64")]
+        // SAFETY: AVX2 が使えることを確かめた
+        return unsafe { avx2::butterfly4(quarters, twiddles, imag) };
+    }
+    butterfly4_scalar(quarters, twiddles, imag);
+}
+
+#[inline]
+pub fn butterfly4_scalar<const MOD: u32>(
+    [a0, a1, a2, a3]: [&mut [ModInt<MOD>]; 4],
+    [t1, t2, t3]: [&[ModInt<MOD>]; 3],
+    imag: ModInt<MOD>,
+) {
+    for j in 0..t1.len() {
+        let c1 = a1[j] * t1[j];
+        let c2 = a2[j] * t2[j];
+        let c3 = a3[j] * t3[j];
+        let (s0, d0) = (a0[j] + c1, a0[j] - c1);
+        let (s1, d1) = (c2 + c3, (c2 - c3) * imag);
+        a0[j] = s0 + s1;
+        a1[j] = d0 + d1;
+        a2[j] = s0 - s1;
+        a3[j] = d0 - d1;
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    use super::{super::ModInt, LANES};
+
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn load<const MOD: u32>(chunk: &[ModInt<MOD>]) -> __m256i {
+        debug_assert_eq!(chunk.len(), LANES);
+        _mm256_loadu_si256(chunk.as_ptr().cast())
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn store<const MOD: u32>(chunk: &mut [ModInt<MOD>], value: __m256i) {
+        debug_assert_eq!(chunk.len(), LANES);
+        _mm256_storeu_si256(chunk.as_mut_ptr().cast(), value)
+    }
+
+    /// `[0, 2 MOD)` の値から `MOD` 以上なら `MOD` を引く. 引いて桁あふれすれば元の値の方が小さい.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn reduce_once<const MOD: u32>(x: __m256i) -> __m256i {
+        _mm256_min_epu32(x, _mm256_sub_epi32(x, _mm256_set1_epi32(MOD as i32)))
+    }
+
+    /// 64 ビットの各レーンの下位 32 ビットどうしの積 `x` について, `(x + (x * N_PRIME mod R) * MOD) / R` を上位 32 ビットに求める.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn reduce_lanes<const MOD: u32>(x: __m256i) -> __m256i {
+        let n_prime = _mm256_set1_epi32(ModInt::<MOD>::N_PRIME as i32);
+        let modulo = _mm256_set1_epi32(MOD as i32);
+        let t = _mm256_mul_epu32(x, n_prime);
+        _mm256_add_epi64(x, _mm256_mul_epu32(t, modulo))
+    }
+
+    /// [`ModInt::reduce`] と同じモンゴメリ乗算を 8 要素に行う.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn mul<const MOD: u32>(a: __m256i, b: __m256i) -> __m256i {
+        let even = reduce_lanes::<MOD>(_mm256_mul_epu32(a, b));
+        let odd = reduce_lanes::<MOD>(_mm256_mul_epu32(
+            _mm256_srli_epi64(a, 32),
+            _mm256_srli_epi64(b, 32),
+        ));
+        let mul = _mm256_blend_epi32(_mm256_srli_epi64(even, 32), odd, 0b1010_1010);
+        reduce_once::<MOD>(mul)
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn add<const MOD: u32>(a: __m256i, b: __m256i) -> __m256i {
+        reduce_once::<MOD>(_mm256_add_epi32(a, b))
+    }
+
+    /// `a < b` なら桁あふれするので, `MOD` を足した方が小さくなる.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn sub<const MOD: u32>(a: __m256i, b: __m256i) -> __m256i {
+        let diff = _mm256_sub_epi32(a, b);
+        _mm256_min_epu32(diff, _mm256_add_epi32(diff, _mm256_set1_epi32(MOD as i32)))
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn mul_assign<const MOD: u32>(lhs: &mut [ModInt<MOD>], rhs: &[ModInt<MOD>]) {
+        let mut lhs_chunks = lhs.chunks_exact_mut(LANES);
+        let mut rhs_chunks = rhs.chunks_exact(LANES);
+        for (l, r) in (&mut lhs_chunks).zip(&mut rhs_chunks) {
+            store(l, mul::<MOD>(load(l), load(r)));
+        }
+        for (l, &r) in lhs_chunks
+            .into_remainder()
+            .iter_mut()
+            .zip(rhs_chunks.remainder())
+        {
+            *l *= r;
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn mul_assign_scalar<const MOD: u32>(vec: &mut [ModInt<MOD>], rhs: ModInt<MOD>) {
+        let r = _mm256_set1_epi32(rhs.0 as i32);
+        let mut chunks = vec.chunks_exact_mut(LANES);
+        for chunk in &mut chunks {
+            store(chunk, mul::<MOD>(load(chunk), r));
+        }
+        for elem in chunks.into_remainder() {
+            *elem *= rhs;
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn butterfly4<const MOD: u32>(
+        [a0, a1, a2, a3]: [&mut [ModInt<MOD>]; 4],
+        [t1, t2, t3]: [&[ModInt<MOD>]; 3],
+        imag: ModInt<MOD>,
+    ) {
+        let imag_lanes = _mm256_set1_epi32(imag.0 as i32);
+        let chunks = t1.len() / LANES * LANES;
+        for j in (0..chunks).step_by(LANES) {
+            let lanes = j..j + LANES;
+            let c1 = mul::<MOD>(load(&a1[lanes.clone()]), load(&t1[lanes.clone()]));
+            let c2 = mul::<MOD>(load(&a2[lanes.clone()]), load(&t2[lanes.clone()]));
+            let c3 = mul::<MOD>(load(&a3[lanes.clone()]), load(&t3[lanes.clone()]));
+            let x0 = load(&a0[lanes.clone()]);
+            let (s0, d0) = (add::<MOD>(x0, c1), sub::<MOD>(x0, c1));
+            let s1 = add::<MOD>(c2, c3);
+            let d1 = mul::<MOD>(sub::<MOD>(c2, c3), imag_lanes);
+            store(&mut a0[lanes.clone()], add::<MOD>(s0, s1));
+            store(&mut a1[lanes.clone()], add::<MOD>(d0, d1));
+            store(&mut a2[lanes.clone()], sub::<MOD>(s0, s1));
+            store(&mut a3[lanes], sub::<MOD>(d0, d1));
+        }
+        super::butterfly4_scalar(
+            [
+                &mut a0[chunks..],
+                &mut a1[chunks..],
+                &mut a2[chunks..],
+                &mut a3[chunks..],
+            ],
+            [&t1[chunks..], &t2[chunks..], &t3[chunks..]],
+            imag,
+        );
+    }
+}
+
+#[test]
+fn batch_matches_scalar() {
+    type M = ModInt<998244353>;
+    // 端数の出る長さにして, 8 要素ずつの部分と残りの両方を確かめる
+    let len = 8 * 5 + 3;
+    let values =
+        |seed: u64| -> Vec<M> {
+            [M::new(0), M::new(M::N as u64 - 1)]
+                .into_iter()
+                .chain((2..len as u64).map(|i| {
+                    M::new((i * 0x9E37_79B9 + seed).wrapping_mul(0xBF58_476D) % M::N as u64)
+                }))
+                .collect()
+        };
+    let a = values(1);
+    let b = values(2);
+    let c = values(3);
+
+    let mut product = a.clone();
+    mul_assign(&mut product, &b);
+    let expected: Vec<_> = a.iter().zip(&b).map(|(&x, &y)| x * y).collect();
+    assert_eq!(product, expected);
+
+    let mut scaled = a.clone();
+    mul_assign_scalar(&mut scaled, c[0]);
+    let expected: Vec<_> = a.iter().map(|&x| x * c[0]).collect();
+    assert_eq!(scaled, expected);
+
+    let mut quarters = [a.clone(), b.clone(), c.clone(), a.clone()];
+    let [q0, q1, q2, q3] = &mut quarters;
+    butterfly4([q0, q1, q2, q3], [&b, &c, &a], c[1]);
+    let mut expected = [a.clone(), b.clone(), c.clone(), a.clone()];
+    let [e0, e1, e2, e3] = &mut expected;
+    butterfly4_scalar([e0, e1, e2, e3], [&b, &c, &a], c[1]);
+    assert_eq!(quarters, expected);
+}
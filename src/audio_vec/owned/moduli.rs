@@ -0,0 +1,165 @@
+use std::{fmt::Debug, hash::Hash};
+
+use super::{mod_int::ModInt, ntt::Ntt};
+
+/// 組み合わせられる法の数の上限. 4 つの法の積でも `u128` に収まる.
+pub const MAX_WIDTH: usize = 4;
+
+/// [`PixelOf`](super::pixel::PixelOf) が剰余を持つ法の組. `ModInt` の 2 つから 4 つの組に実装する.
+///
+/// 法はどれも数論変換のできる素数で, 互いに異なること.
+pub trait Moduli: Copy + Default + Eq + Hash + Debug {
+    /// 法の一覧.
+    const MODULI: &'static [u32];
+    /// 法の積. これを法とした値を復元できる.
+    const MODULUS: u128 = product(Self::MODULI);
+    /// Garner のアルゴリズムの係数. `[i]` は `MODULI[..i]` の積の, `MODULI[i]` を法とした逆数.
+    const GARNER: [u32; MAX_WIDTH] = garner_inverses(Self::MODULI);
+
+    /// 法ごとの数論変換.
+    type Ntts: Default + Debug;
+    /// 法ごとに数論変換した列.
+    type Spectrum: Clone + Debug;
+
+    fn from_signed(value: i64) -> Self;
+    fn from_i128(value: i128) -> Self;
+    /// `i` 番目の法での剰余.
+    fn residue(self, i: usize) -> u32;
+    fn add(self, rhs: Self) -> Self;
+    fn sub(self, rhs: Self) -> Self;
+    fn mul(self, rhs: Self) -> Self;
+    /// `vec` を長さ `len` まで 0 で埋めて, 法ごとに数論変換する. `len` は 2 のべき乗であること.
+    fn spectrum(vec: &[Self], len: usize, ntts: &Self::Ntts) -> Self::Spectrum;
+    /// `vec` と, 変換済みの `spectrum` の巡回畳み込み. 長さは `spectrum` と同じになる.
+    fn cyclic_convolution(vec: &[Self], spectrum: &Self::Spectrum, ntts: &Self::Ntts) -> Vec<Self>;
+
+    /// 法ごとに数論変換で畳み込む. どちらかが空なら空.
+    fn convolution(lhs: &[Self], rhs: &[Self], ntts: &Self::Ntts) -> Vec<Self> {
+        if lhs.is_empty() || rhs.is_empty() {
+            return vec![];
+        }
+        let len = lhs.len() + rhs.len() - 1;
+        let spectrum = Self::spectrum(rhs, len.next_power_of_two(), ntts);
+        let mut convolution = Self::cyclic_convolution(lhs, &spectrum, ntts);
+        convolution.truncate(len);
+        convolution
+    }
+}
+
+const fn product(moduli: &[u32]) -> u128 {
+    assert!(moduli.len() <= MAX_WIDTH);
+    let mut product = 1;
+    let mut i = 0;
+    while i < moduli.len() {
+        product *= moduli[i] as u128;
+        i += 1;
+    }
+    product
+}
+
+const fn pow_mod(mut base: u64, mut exp: u64, modulo: u64) -> u64 {
+    let mut result = 1 % modulo;
+    base %= modulo;
+    while 0 < exp {
+        if exp % 2 == 1 {
+            result = result * base % modulo;
+        }
+        base = base * base % modulo;
+        exp /= 2;
+    }
+    result
+}
+
+const fn garner_inverses(moduli: &[u32]) -> [u32; MAX_WIDTH] {
+    let mut inverses = [0; MAX_WIDTH];
+    let mut i = 0;
+    while i < moduli.len() {
+        let modulo = moduli[i] as u64;
+        let mut prefix = 1 % modulo;
+        let mut j = 0;
+        while j < i {
+            assert!(moduli[i] != moduli[j], "duplicated modulo");
+            prefix = prefix * (moduli[j] as u64 % modulo) % modulo;
+            j += 1;
+        }
+        inverses[i] = pow_mod(prefix, modulo - 2, modulo) as u32;
+        i += 1;
+    }
+    inverses
+}
+
+/// 法の数を `N` として, `(ModInt<M0>, ..., ModInt<M{N-1}>)` に [`Moduli`] を実装する.
+macro_rules! impl_moduli {
+    ($($i:tt $m:ident),+) => {
+        impl<$(const $m: u32),+> Moduli for ($(ModInt<$m>,)+) {
+            const MODULI: &'static [u32] = &[$($m),+];
+
+            type Ntts = ($(Ntt<$m>,)+);
+            type Spectrum = ($(Vec<ModInt<$m>>,)+);
+
+            #[inline]
+            fn from_signed(value: i64) -> Self {
+                ($(ModInt::<$m>::from_signed(value),)+)
+            }
+
+            #[inline]
+            fn from_i128(value: i128) -> Self {
+                ($(ModInt::<$m>::new(value.rem_euclid($m as i128) as u64),)+)
+            }
+
+            #[inline]
+            fn residue(self, i: usize) -> u32 {
+                match i {
+                    $($i => self.$i.as_u32(),)+
+                    _ => panic!("no modulo at {i}"),
+                }
+            }
+
+            #[inline]
+            fn add(self, rhs: Self) -> Self {
+                ($(self.$i + rhs.$i,)+)
+            }
+
+            #[inline]
+            fn sub(self, rhs: Self) -> Self {
+                ($(self.$i - rhs.$i,)+)
+            }
+
+            #[inline]
+            fn mul(self, rhs: Self) -> Self {
+                ($(self.$i * rhs.$i,)+)
+            }
+
+            fn spectrum(vec: &[Self], len: usize, ntts: &Self::Ntts) -> Self::Spectrum {
+                ($({
+                    let mut lane: Vec<_> = vec.iter().map(|x| x.$i).collect();
+                    lane.resize(len, Default::default());
+                    ntts.$i.transform(&mut lane);
+                    lane
+                },)+)
+            }
+
+            fn cyclic_convolution(
+                vec: &[Self],
+                spectrum: &Self::Spectrum,
+                ntts: &Self::Ntts,
+            ) -> Vec<Self> {
+                let len = spectrum.0.len();
+                assert!(vec.len() <= len);
+                let lanes = ($({
+                    let mut lane: Vec<_> = vec.iter().map(|x| x.$i).collect();
+                    lane.resize(len, Default::default());
+                    ntts.$i.transform(&mut lane);
+                    ModInt::mul_assign_slice(&mut lane, &spectrum.$i);
+                    ntts.$i.inverse_transform(&mut lane);
+                    lane
+                },)+);
+                (0..len).map(|k| ($(lanes.$i[k],)+)).collect()
+            }
+        }
+    };
+}
+
+impl_moduli!(0 M0, 1 M1);
+impl_moduli!(0 M0, 1 M1, 2 M2);
+impl_moduli!(0 M0, 1 M1, 2 M2, 3 M3);
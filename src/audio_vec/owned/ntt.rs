@@ -1,14 +1,25 @@
-use num::traits::Pow;
+use std::{
+    ops::{Add, Mul, MulAssign, Sub},
+    sync::OnceLock,
+};
+
+use num::{traits::Pow, One};
 
 use super::mod_int::ModInt;
 
+pub use self::plan::NttPlan;
+
+mod plan;
 #[cfg(test)]
 mod tests;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone)]
 pub struct Ntt<const MOD: u32> {
+    /// `root_of_power_of_2[k]` は 1 の原始 2^k 乗根.
     root_of_power_of_2: Vec<ModInt<MOD>>,
     inv_root_of_power_of_2: Vec<ModInt<MOD>>,
+    /// `plans[k]` は長さ 2^k の変換の前計算. 初めて使うときに作り, 読み札どうしで使い回す.
+    plans: Vec<OnceLock<NttPlan<MOD>>>,
 }
 
 impl<const MOD: u32> Ntt<MOD> {
@@ -18,8 +29,8 @@ impl<const MOD: u32> Ntt<MOD> {
     pub fn new() -> Self {
         let modulo = MOD;
         let primitive_root = primitive_root(modulo);
-        let root_of_power_of_2: Vec<_> = (0..Self::LEVEL)
-            .map(|i| primitive_root.pow(1 << i))
+        let root_of_power_of_2: Vec<_> = (0..=Self::LEVEL)
+            .map(|k| primitive_root.pow((MOD - 1) >> k))
             .collect();
         let inv_root_of_power_of_2 = root_of_power_of_2
             .iter()
@@ -29,59 +40,36 @@ impl<const MOD: u32> Ntt<MOD> {
         Self {
             root_of_power_of_2,
             inv_root_of_power_of_2,
+            plans: (0..=Self::LEVEL).map(|_| OnceLock::new()).collect(),
         }
     }
 
+    /// 長さ `len` の変換の前計算. `len` は 2 のべき乗であること.
+    pub fn plan(&self, len: usize) -> &NttPlan<MOD> {
+        assert_eq!(len.count_ones(), 1);
+        let level = len.trailing_zeros() as usize;
+        assert!(level <= Self::LEVEL, "too long to transform: {len}");
+        self.plans[level].get_or_init(|| {
+            NttPlan::new(
+                level,
+                &self.root_of_power_of_2,
+                &self.inv_root_of_power_of_2,
+            )
+        })
+    }
+
     pub fn transform(&self, vec: &mut [ModInt<MOD>]) {
-        let vec_len = vec.len();
-        if vec_len <= 1 {
+        if vec.len() <= 1 {
             return;
         }
-        assert_eq!(vec_len.count_ones(), 1);
-
-        let vec_len_width = vec_len.trailing_zeros() as usize - 1;
-        let mut window_width = 1 << (vec_len_width - 1);
-        for &root in self.root_of_power_of_2[..vec_len_width].iter().rev() {
-            for left in (0..vec_len).step_by(2 * window_width) {
-                let mut root_i = ModInt::new(1);
-                for i in left..left + window_width {
-                    let vec_i = vec[i];
-                    let vec_i_next = vec[i + window_width];
-                    vec[i] = vec_i + vec_i_next;
-                    vec[i + window_width] = vec_i + vec_i_next;
-                    root_i *= root;
-                }
-            }
-            window_width /= 2;
-        }
+        self.plan(vec.len()).transform(vec);
     }
 
     pub fn inverse_transform(&self, vec: &mut [ModInt<MOD>]) {
-        let vec_len = vec.len();
-        if vec_len <= 1 {
+        if vec.len() <= 1 {
             return;
         }
-        assert_eq!(vec_len.count_ones(), 1);
-
-        let vec_len_width = (vec_len - 1).trailing_zeros() as usize;
-        let mut window_width = 1;
-        for &inv_root in &self.inv_root_of_power_of_2[1..vec_len_width + 1] {
-            for left in (0..vec_len).step_by(2 * window_width) {
-                let mut inv_root_i = ModInt::new(1);
-                for i in left..left + window_width {
-                    let vec_i = vec[i];
-                    let vec_i_next = vec[i + window_width];
-                    vec[i] = vec_i + vec_i_next * inv_root_i;
-                    vec[i + window_width] = vec_i - vec_i_next * inv_root_i;
-                    inv_root_i *= inv_root;
-                }
-            }
-            window_width *= 2;
-        }
-        let inv_vec_len = ModInt::new(vec_len as u64).inv();
-        for elem in &mut vec[..] {
-            *elem *= inv_vec_len;
-        }
+        self.plan(vec.len()).inverse_transform(vec);
     }
 }
 
@@ -91,6 +79,66 @@ impl<const MOD: u32> Default for Ntt<MOD> {
     }
 }
 
+/// バタフライ演算の 1 段. 既定では 1 要素ずつ計算する.
+pub trait Butterfly:
+    Copy + Add<Output = Self> + Sub<Output = Self> + MulAssign + Mul<Output = Self> + One
+{
+    /// `vec` を幅 `2 * twiddles.len()` の窓に区切り, 窓の前半 `lo` と後半 `hi` を `(lo + hi * twiddle, lo - hi * twiddle)` にする.
+    fn butterfly_stage(vec: &mut [Self], twiddles: &[Self]) {
+        for window in vec.chunks_exact_mut(2 * twiddles.len()) {
+            let (lo, hi) = window.split_at_mut(twiddles.len());
+            butterfly_scalar(lo, hi, twiddles);
+        }
+    }
+}
+
+#[inline]
+fn butterfly_scalar<T: Butterfly>(lo: &mut [T], hi: &mut [T], twiddles: &[T]) {
+    for ((l, h), &twiddle) in lo.iter_mut().zip(hi).zip(twiddles) {
+        let u = *l;
+        let v = *h * twiddle;
+        *l = u + v;
+        *h = u - v;
+    }
+}
+
+/// ビット反転で並べ替えてから, 窓幅を 2 倍ずつ広げながらバタフライ演算を行う.
+///
+/// `roots[k]` は 1 の原始 2^k 乗根. 数論変換は [`NttPlan`] で行うので, 複素数の高速フーリエ変換で使う.
+pub fn butterfly<T: Butterfly>(vec: &mut [T], roots: &[T]) {
+    let vec_len = vec.len();
+    if vec_len <= 1 {
+        return;
+    }
+    assert_eq!(vec_len.count_ones(), 1);
+    let vec_len_width = vec_len.trailing_zeros() as usize;
+    assert!(
+        vec_len_width < roots.len(),
+        "too long to transform: {vec_len}"
+    );
+
+    for i in 0..vec_len {
+        let j = i.reverse_bits() >> (usize::BITS as usize - vec_len_width);
+        if i < j {
+            vec.swap(i, j);
+        }
+    }
+
+    // 段ごとに回転因子 root^i を並べておく
+    let mut twiddles = Vec::with_capacity(vec_len / 2);
+    let mut window_width = 1;
+    for &root in &roots[1..=vec_len_width] {
+        twiddles.clear();
+        let mut root_i = T::one();
+        for _ in 0..window_width {
+            twiddles.push(root_i);
+            root_i *= root;
+        }
+        T::butterfly_stage(vec, &twiddles);
+        window_width *= 2;
+    }
+}
+
 fn primitive_root<const MOD: u32>(modulo: u32) -> ModInt<MOD> {
     if modulo == 2 {
         return ModInt::new(1);
@@ -102,9 +150,9 @@ fn primitive_root<const MOD: u32>(modulo: u32) -> ModInt<MOD> {
         if m < i * i {
             break;
         }
-        if m % i == 0 {
+        if m.is_multiple_of(i) {
             divisors.push(i as u64);
-            while m % i == 0 {
+            while m.is_multiple_of(i) {
                 m /= i;
             }
         }
@@ -119,7 +167,7 @@ fn primitive_root<const MOD: u32>(modulo: u32) -> ModInt<MOD> {
             let mut b: u64 = (modulo as u64 - 1) / divisor;
             let mut r: u64 = 1;
             while b != 0 {
-                if b % 2 != 0 {
+                if !b.is_multiple_of(2) {
                     r *= a;
                     r %= modulo as u64;
                 }
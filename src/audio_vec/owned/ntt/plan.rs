@@ -0,0 +1,143 @@
+use num::{traits::Pow, One};
+
+use crate::audio_vec::owned::mod_int::ModInt;
+
+/// 長さ `2^level` の数論変換の前計算. ビット反転の並べ替えと, 段ごとの回転因子の表を持つ.
+///
+/// 基数 4 のバタフライ演算で 2 段ずつ変換する. 段の数が奇数なら, 最初の 1 段だけ基数 2 で行う.
+///
+/// 基数 4 の 1 回では, 3 つの回転因子と定数 ω_4 を 1 回ずつ掛ける.
+#[derive(Debug, Clone)]
+pub struct NttPlan<const MOD: u32> {
+    level: usize,
+    /// `bit_reverse[i]` は `i` の下位 `level` ビットを反転した数
+    bit_reverse: Vec<u32>,
+    /// 窓の半分の幅が `w` の段の回転因子 `ω_{2w}^j (0 <= j < w)` を `twiddles[w - 1..2w - 1]` に並べたもの
+    twiddles: Vec<ModInt<MOD>>,
+    inv_twiddles: Vec<ModInt<MOD>>,
+    /// 窓の 4 分の 1 の幅が `w` の基数 4 の段で 4 つ目に掛ける `ω_{4w}^{3j} (0 <= j < w)` を `cube_twiddles[w - 1..2w - 1]` に並べたもの
+    cube_twiddles: Vec<ModInt<MOD>>,
+    inv_cube_twiddles: Vec<ModInt<MOD>>,
+    /// 1 の原始 4 乗根 ω_4 とその逆数
+    imag: ModInt<MOD>,
+    inv_imag: ModInt<MOD>,
+    inv_len: ModInt<MOD>,
+}
+
+impl<const MOD: u32> NttPlan<MOD> {
+    /// `roots[k]` と `inv_roots[k]` は 1 の原始 2^k 乗根とその逆数.
+    pub fn new(level: usize, roots: &[ModInt<MOD>], inv_roots: &[ModInt<MOD>]) -> Self {
+        assert!(level < roots.len(), "too long to transform: 2^{level}");
+        let len = 1usize << level;
+        let bit_reverse = (0..len as u32)
+            .map(|i| {
+                i.reverse_bits()
+                    .checked_shr(u32::BITS - level as u32)
+                    .unwrap_or(0)
+            })
+            .collect();
+        let table = |roots: &[ModInt<MOD>]| {
+            let mut twiddles = Vec::with_capacity(len.saturating_sub(1));
+            for &root in &roots[1..=level] {
+                let width = twiddles.len() + 1;
+                let mut root_j = ModInt::one();
+                for _ in 0..width {
+                    twiddles.push(root_j);
+                    root_j *= root;
+                }
+            }
+            twiddles
+        };
+        let cube_table = |roots: &[ModInt<MOD>]| {
+            let mut twiddles = Vec::with_capacity((len / 2).saturating_sub(1));
+            for &root in roots.iter().take(level + 1).skip(2) {
+                let width = twiddles.len() + 1;
+                let cube = root.pow(3);
+                let mut cube_j = ModInt::one();
+                for _ in 0..width {
+                    twiddles.push(cube_j);
+                    cube_j *= cube;
+                }
+            }
+            twiddles
+        };
+        let imag = |roots: &[ModInt<MOD>]| roots.get(2).copied().unwrap_or_else(ModInt::one);
+        Self {
+            level,
+            bit_reverse,
+            twiddles: table(roots),
+            inv_twiddles: table(inv_roots),
+            cube_twiddles: cube_table(roots),
+            inv_cube_twiddles: cube_table(inv_roots),
+            imag: imag(roots),
+            inv_imag: imag(inv_roots),
+            inv_len: ModInt::new(len as u64).inv(),
+        }
+    }
+
+    /// 変換する長さの 2 を底とする対数.
+    #[inline]
+    pub fn level(&self) -> usize {
+        self.level
+    }
+
+    pub fn transform(&self, vec: &mut [ModInt<MOD>]) {
+        self.permute(vec);
+        Self::stages(vec, &self.twiddles, &self.cube_twiddles, self.imag);
+    }
+
+    pub fn inverse_transform(&self, vec: &mut [ModInt<MOD>]) {
+        self.permute(vec);
+        Self::stages(
+            vec,
+            &self.inv_twiddles,
+            &self.inv_cube_twiddles,
+            self.inv_imag,
+        );
+        ModInt::mul_assign_scalar(vec, self.inv_len);
+    }
+
+    fn permute(&self, vec: &mut [ModInt<MOD>]) {
+        assert_eq!(vec.len(), 1 << self.level);
+        for (i, &j) in self.bit_reverse.iter().enumerate() {
+            let j = j as usize;
+            if i < j {
+                vec.swap(i, j);
+            }
+        }
+    }
+
+    fn stages(
+        vec: &mut [ModInt<MOD>],
+        twiddles: &[ModInt<MOD>],
+        cube_twiddles: &[ModInt<MOD>],
+        imag: ModInt<MOD>,
+    ) {
+        let len = vec.len();
+        let mut width = 1;
+        if len.trailing_zeros() % 2 == 1 {
+            // 回転因子は 1 だけ
+            for pair in vec.chunks_exact_mut(2) {
+                let (u, v) = (pair[0], pair[1]);
+                pair[0] = u + v;
+                pair[1] = u - v;
+            }
+            width = 2;
+        }
+        while width < len {
+            // ω_{2w}^j, ω_{4w}^j, ω_{4w}^{3j}
+            let twiddles = [
+                &twiddles[width - 1..2 * width - 1],
+                &twiddles[2 * width - 1..3 * width - 1],
+                &cube_twiddles[width - 1..2 * width - 1],
+            ];
+            for window in vec.chunks_exact_mut(4 * width) {
+                let (half0, half1) = window.split_at_mut(2 * width);
+                let (a0, a1) = half0.split_at_mut(width);
+                let (a2, a3) = half1.split_at_mut(width);
+                ModInt::butterfly4_slices([a0, a1, a2, a3], twiddles, imag);
+            }
+            width *= 4;
+        }
+    }
+}
@@ -1,6 +1,8 @@
 //! From: https://judge.yosupo.jp/problem/convolution_mod
 
-use crate::audio_vec::owned::{pixel::Pixel, Owned};
+use num::traits::Pow;
+
+use crate::audio_vec::owned::{mod_int::ModInt, pixel::Pixel, Owned};
 
 use super::Ntt;
 
@@ -11,9 +13,8 @@ fn convolution1() {
 
     let a_audio = Owned::from_raw_slice(&a);
     let b_audio = Owned::from_raw_slice(&b);
-    let ntt1 = Ntt::new();
-    let ntt2 = Ntt::new();
-    let out = a_audio.convolution(&b_audio, (&ntt1, &ntt2));
+    let ntts = (Ntt::new(), Ntt::new());
+    let out = a_audio.convolution(&b_audio, &ntts);
 
     let expected: Vec<_> = [5, 16, 34, 60, 70, 70, 59, 36]
         .into_iter()
@@ -29,9 +30,8 @@ fn convolution2() {
 
     let a_audio = Owned::from_raw_slice(&a);
     let b_audio = Owned::from_raw_slice(&b);
-    let ntt1 = Ntt::new();
-    let ntt2 = Ntt::new();
-    let out = a_audio.convolution(&b_audio, (&ntt1, &ntt2));
+    let ntts = (Ntt::new(), Ntt::new());
+    let out = a_audio.convolution(&b_audio, &ntts);
 
     let expected = vec![Pixel::from_unsigned(100000000000000)];
     assert_eq!(out, expected);
@@ -50,9 +50,8 @@ fn convolution3() {
 
     let a_audio = Owned::from_raw_slice(&a);
     let b_audio = Owned::from_raw_slice(&b);
-    let ntt1 = Ntt::new();
-    let ntt2 = Ntt::new();
-    let out = a_audio.convolution(&b_audio, (&ntt1, &ntt2));
+    let ntts = (Ntt::new(), Ntt::new());
+    let out = a_audio.convolution(&b_audio, &ntts);
 
     assert_eq!(out, ugly_convolution(&a_audio, &b_audio));
 }
@@ -67,3 +66,102 @@ fn ugly_convolution(a: &Owned, b: &Owned) -> Vec<Pixel> {
     }
     res
 }
+
+#[test]
+fn convolution_ntt() {
+    // 段の数が奇数 (2048) と偶数 (4096) の長さになる組
+    let ntts = (Ntt::new(), Ntt::new());
+    for (a_len, b_len) in [(1000, 300), (3000, 1000)] {
+        let a: Vec<_> = (0..a_len).map(|i| i * 7919 % 65536).collect();
+        let b: Vec<_> = (0..b_len).map(|i| i * 104729 % 65536).collect();
+
+        let a_audio = Owned::from_raw_slice(&a);
+        let b_audio = Owned::from_raw_slice(&b);
+        let out = a_audio.convolution(&b_audio, &ntts);
+
+        assert_eq!(out, ugly_convolution(&a_audio, &b_audio));
+    }
+}
+
+fn plan_matches_dft<const MOD: u32>() {
+    let ntt = Ntt::<MOD>::new();
+    for level in 0..=7 {
+        let len = 1 << level;
+        let input: Vec<_> = (0..len as u64)
+            .map(|i| ModInt::<MOD>::new(i * 7919 % 65536))
+            .collect();
+        let mut output = input.clone();
+        let plan = ntt.plan(len);
+        assert_eq!(plan.level(), level);
+        plan.transform(&mut output);
+
+        let root = ntt.root_of_power_of_2[level];
+        let expected: Vec<_> = (0..len as u32)
+            .map(|k| {
+                input
+                    .iter()
+                    .enumerate()
+                    .map(|(t, &x)| x * root.pow(k * t as u32))
+                    .sum::<ModInt<MOD>>()
+            })
+            .collect();
+        assert_eq!(output, expected, "length {len}");
+
+        plan.inverse_transform(&mut output);
+        assert_eq!(output, input, "length {len}");
+    }
+}
+
+#[test]
+fn plan_matches_dft_924844033() {
+    plan_matches_dft::<924844033>();
+}
+
+#[test]
+fn plan_matches_dft_998244353() {
+    plan_matches_dft::<998244353>();
+}
+
+#[test]
+fn plan_is_reused() {
+    let ntt = Ntt::<998244353>::new();
+    let plan = ntt.plan(1 << 12) as *const _;
+    let mut vec: Vec<_> = (0..1 << 12).map(ModInt::new).collect();
+    let input = vec.clone();
+    ntt.transform(&mut vec);
+    ntt.inverse_transform(&mut vec);
+    assert_eq!(vec, input);
+    assert_eq!(ntt.plan(1 << 12) as *const _, plan);
+}
+
+/// 読み上げ音声の 2 乗どうしの畳み込みは, 2 つの法では桁あふれする.
+fn squared_samples(len: i64, seed: i64) -> Owned {
+    Owned::from_pixels((0..len).map(|i| {
+        let sample = (i * seed) % 65536 - 32768;
+        Pixel::from_signed(sample * sample * if i % 3 == 0 { -1 } else { 1 })
+    }))
+}
+
+#[test]
+fn exact_convolution_picks_width() {
+    let a = squared_samples(1000, 7919);
+    let b = squared_samples(300, 104729);
+    let expected: Vec<i128> = (0..a.len() + b.len() - 1)
+        .map(|k| {
+            (k.saturating_sub(b.len() - 1)..a.len().min(k + 1))
+                .map(|i| a.vec[i].as_i128() * b.vec[k - i].as_i128())
+                .sum()
+        })
+        .collect();
+    assert!(expected.iter().any(|&x| Pixel::MAX_ABS < x.unsigned_abs()));
+
+    assert_eq!(a.exact_convolution(&b, &Default::default()), expected);
+}
+
+#[test]
+#[should_panic(expected = "convolution overflows 2 moduli")]
+fn convolution_detects_overflow() {
+    let a = squared_samples(1000, 7919);
+    let b = squared_samples(300, 104729);
+    a.convolution(&b, &(Ntt::new(), Ntt::new()));
+}
@@ -0,0 +1,150 @@
+use super::{
+    moduli::Moduli,
+    pixel::{max_abs, required_width, PixelModuli, PixelOf},
+    Owned,
+};
+
+/// [`OverlapSave`] で畳み込む `kernel` を変換しておいたもの. 同じ `kernel` で何度も畳み込むなら使い回せる.
+#[derive(Debug, Clone)]
+pub struct OverlapSaveKernel<M: Moduli = PixelModuli> {
+    len: usize,
+    max: u128,
+    /// 変換済みの `kernel`
+    spectrum: M::Spectrum,
+    /// 数論変換の長さ
+    block_len: usize,
+}
+
+impl<M: Moduli> OverlapSaveKernel<M> {
+    /// 数論変換の長さは `block_len` と `2 * kernel.len()` の大きい方を 2 のべき乗に切り上げたもの.
+    pub fn new(kernel: &Owned, block_len: usize, ntts: &M::Ntts) -> Self {
+        assert!(!kernel.is_empty(), "empty kernel");
+        let kernel: Vec<_> = kernel.vec.iter().map(|px| px.convert::<M>()).collect();
+        let block_len = block_len.max(2 * kernel.len()).next_power_of_two();
+        let residues: Vec<_> = kernel.iter().map(|px| px.into_inner()).collect();
+        Self {
+            len: kernel.len(),
+            max: max_abs(&kernel),
+            spectrum: M::spectrum(&residues, block_len, ntts),
+            block_len,
+        }
+    }
+}
+
+/// 長い入力を区切りながら, 短い `kernel` と畳み込む (overlap-save 法).
+///
+/// 数論変換の長さは入力の長さによらず一定なので, 入力を少しずつ [`Self::push`] しながら結果を受け取れる.
+/// 区間ごとに直前の入力の末尾 `kernel.len() - 1` 個を重ねて巡回畳み込みをし, 巡回の影響を受けない部分だけを使う.
+pub struct OverlapSave<'a, M: Moduli = PixelModuli> {
+    ntts: &'a M::Ntts,
+    kernel: &'a OverlapSaveKernel<M>,
+    /// 直前までの入力の末尾 `kernel.len - 1` 個と, まだ畳み込んでいない入力
+    buffer: Vec<M>,
+    input_max: u128,
+}
+
+impl<'a, M: Moduli> OverlapSave<'a, M> {
+    pub fn new(kernel: &'a OverlapSaveKernel<M>, ntts: &'a M::Ntts) -> Self {
+        Self {
+            ntts,
+            kernel,
+            buffer: vec![M::default(); kernel.len - 1],
+            input_max: 0,
+        }
+    }
+
+    /// 入力の続きを受け取り, 新たに確定した出力を返す. 出力は, それまでに返した出力の続きになる.
+    pub fn push(&mut self, input: &Owned) -> Vec<PixelOf<M>> {
+        let input: Vec<_> = input.vec.iter().map(|px| px.convert::<M>()).collect();
+        self.input_max = self.input_max.max(max_abs(&input));
+        let kernel = self.kernel;
+        assert!(
+            PixelOf::<M>::fits_convolution(kernel.len, kernel.max, kernel.len, self.input_max),
+            "convolution overflows {} moduli, needs {:?}",
+            M::MODULI.len(),
+            required_width(kernel.len, kernel.max, kernel.len, self.input_max),
+        );
+        self.buffer
+            .extend(input.into_iter().map(PixelOf::into_inner));
+
+        let mut output = vec![];
+        while kernel.block_len <= self.buffer.len() {
+            self.convolve_block(&mut output);
+        }
+        output
+    }
+
+    /// 入力の終わりを知らせ, 残りの出力を返す. 出力の長さの合計は, 入力の長さの合計 + `kernel.len() - 1` になる.
+    pub fn finish(mut self) -> Vec<PixelOf<M>> {
+        self.buffer
+            .extend(std::iter::repeat_n(M::default(), self.kernel.len - 1));
+        let mut output = vec![];
+        while self.kernel.len - 1 < self.buffer.len() {
+            self.convolve_block(&mut output);
+        }
+        output
+    }
+
+    /// `buffer` の先頭の区間を畳み込み, 重ねた分の後ろから確定した出力を `output` に加える.
+    fn convolve_block(&mut self, output: &mut Vec<PixelOf<M>>) {
+        let block = &self.buffer[..self.kernel.block_len.min(self.buffer.len())];
+        let overlap = self.kernel.len - 1;
+        let fresh = block.len() - overlap;
+        let convolution = M::cyclic_convolution(block, &self.kernel.spectrum, self.ntts);
+        // SAFETY: 各法での同じ畳み込み演算の結果であり、整合性が保たれている。
+        let pixels = convolution[overlap..overlap + fresh]
+            .iter()
+            .map(|&residues| unsafe { PixelOf::from_inner(residues) });
+        output.extend(pixels);
+        self.buffer.drain(..fresh);
+    }
+}
+
+#[cfg(test)]
+fn samples(len: i64, seed: i64) -> Owned {
+    Owned::from_pcm(
+        &(0..len)
+            .map(|i| ((i * seed) % 65536 - 32768) as i16)
+            .collect::<Vec<_>>(),
+    )
+}
+
+#[test]
+fn overlap_save_matches_convolution() {
+    let ntts = Default::default();
+    // 入力が読み札より長い場合, 短い場合, 区間が 2 * kernel より短く指定された場合
+    for (input_len, kernel_len, block_len) in [(5000, 300, 1024), (200, 700, 4096), (3000, 900, 64)]
+    {
+        let input = samples(input_len, 7919);
+        let kernel = samples(kernel_len, 104729);
+        assert_eq!(
+            input.overlap_save(&kernel, block_len, &ntts),
+            input.convolution(&kernel, &ntts),
+            "{input_len} {kernel_len} {block_len}"
+        );
+    }
+}
+
+#[test]
+fn overlap_save_streams_chunks() {
+    use crate::audio_vec::AudioVec;
+
+    let ntts = Default::default();
+    let input = samples(6000, 7919);
+    let kernel = samples(500, 104729);
+
+    let kernel_spectrum = OverlapSaveKernel::<PixelModuli>::new(&kernel, 2048, &ntts);
+    let mut stream = OverlapSave::new(&kernel_spectrum, &ntts);
+    let mut output = vec![];
+    let mut start = 0;
+    for chunk_len in [1, 999, 3000, 0, 1500, 500] {
+        let chunk = (&input).window(start, chunk_len).to_owned(chunk_len);
+        let pushed = stream.push(&chunk);
+        // 確定した出力は, それまでの入力だけで決まる
+        assert!(output.len() + pushed.len() <= start as usize + chunk_len);
+        output.extend(pushed);
+        start += chunk_len as isize;
+    }
+    output.extend(stream.finish());
+    assert_eq!(output, input.convolution(&kernel, &ntts));
+}
@@ -1,106 +1,308 @@
-use cast::{i64, u64};
-use num::{integer::ExtendedGcd, Integer};
 use serde::{Deserialize, Serialize};
 use std::ops;
 
-use super::mod_int::{ModInt, ModInt924844033, ModInt998244353};
+use super::{
+    mod_int::{ModInt167772161, ModInt469762049, ModInt924844033, ModInt998244353},
+    moduli::{Moduli, MAX_WIDTH},
+};
 
-/// 924844033 と 924844033 の 2 種類の法における剰余を格納する. Garner のアルゴリズムにより 924844033 × 998244353 = 923220333347995649 を法とした値を求める.
+pub type PixelModuli = (ModInt924844033, ModInt998244353);
+pub type Pixel3Moduli = (ModInt924844033, ModInt998244353, ModInt469762049);
+pub type Pixel4Moduli = (
+    ModInt924844033,
+    ModInt998244353,
+    ModInt469762049,
+    ModInt167772161,
+);
+
+/// 924844033 と 998244353 の 2 種類の法における剰余を格納する. Garner のアルゴリズムにより 924844033 × 998244353 = 923220333347995649 を法とした値を求める.
+pub type Pixel = PixelOf<PixelModuli>;
+/// [`Pixel`] に 469762049 を加えて, 約 4.3e26 を法とした値を求める.
+pub type Pixel3 = PixelOf<Pixel3Moduli>;
+/// [`Pixel3`] に 167772161 を加えて, 約 7.2e34 を法とした値を求める.
+pub type Pixel4 = PixelOf<Pixel4Moduli>;
+
+/// 法の組 `M` のそれぞれにおける剰余を格納する. Garner のアルゴリズムにより法の積を法とした値を求める.
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub struct Pixel(ModInt924844033, ModInt998244353);
+pub struct PixelOf<M>(M);
 
-impl PartialOrd for Pixel {
+impl<M: Moduli> PartialOrd for PixelOf<M> {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         Some(self.cmp(other))
     }
 }
 
-impl Ord for Pixel {
+impl<M: Moduli> Ord for PixelOf<M> {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.as_u64().cmp(&other.as_u64())
+        self.as_u128().cmp(&other.as_u128())
     }
 }
 
-fn garner(m1: ModInt924844033, m2: ModInt998244353) -> u64 {
-    let r1 = m1.as_u32();
-    let m1 = ModInt924844033::N;
-    let r2 = m2.as_u32();
-    let m2 = ModInt998244353::N;
-    let (ExtendedGcd { gcd, x, .. }, lcm) = i64(m1).extended_gcd_lcm(&i64(m2));
-    // 924844033 * x + 998244353 * y = gcd = 1
-    debug_assert_eq!(gcd, 1, "not co-prime modulo");
-    let diff = r1.abs_diff(r2);
-    let tmp = i64(diff) / gcd * x % (i64(m2) / gcd);
-    u64((i64(r1) + i64(m1) * tmp).rem_euclid(lcm)).unwrap()
+/// `x = Σ_i v_i (m_0 ... m_{i-1})` となる `0 <= v_i < m_i` を法の小さい添字から順に求める.
+fn garner<M: Moduli>(residues: M) -> u128 {
+    let moduli = M::MODULI;
+    let mut digits = [0u64; MAX_WIDTH];
+    for i in 0..moduli.len() {
+        let modulo = moduli[i] as u64;
+        // それまでの桁で表した値の, m_i を法とした剰余
+        let mut value = 0;
+        let mut prefix = 1;
+        for (&digit, &prev) in digits[..i].iter().zip(moduli) {
+            value = (value + digit * prefix) % modulo;
+            prefix = prefix * prev as u64 % modulo;
+        }
+        let diff = (residues.residue(i) as u64 + modulo - value) % modulo;
+        digits[i] = diff * M::GARNER[i] as u64 % modulo;
+    }
+    let mut value = 0;
+    let mut prefix = 1;
+    for (&digit, &modulo) in digits.iter().zip(moduli) {
+        value += digit as u128 * prefix;
+        prefix *= modulo as u128;
+    }
+    value
 }
 
-impl Pixel {
+impl<M: Moduli> PixelOf<M> {
+    /// 符号付きで正確に表せる絶対値の上限.
+    pub const MAX_ABS: u128 = M::MODULUS / 2;
+
     #[inline]
     pub fn from_signed(value: i64) -> Self {
-        Self(ModInt::from_signed(value), ModInt::from_signed(value))
+        Self(M::from_signed(value))
+    }
+
+    #[inline]
+    pub fn from_i128(value: i128) -> Self {
+        Self(M::from_i128(value))
     }
 
     #[inline]
     #[cfg(test)]
     pub fn from_unsigned(value: u64) -> Self {
-        Self(ModInt::new(value), ModInt::new(value))
+        Self(M::from_i128(value as i128))
+    }
+
+    #[inline]
+    pub fn as_u128(self) -> u128 {
+        garner(self.0)
     }
 
+    /// 法の積が `u64` に収まる組で使う. 収まらない値は下位の桁だけになる.
     #[inline]
     pub fn as_u64(self) -> u64 {
-        garner(self.0, self.1)
+        self.as_u128() as u64
     }
 
+    /// 法の積の半分より大きい値を負の数とみなして符号付き整数に変換する.
     #[inline]
-    pub fn into_inner(self) -> (ModInt924844033, ModInt998244353) {
-        (self.0, self.1)
+    pub fn as_i128(self) -> i128 {
+        if let Some(value) = self.small_i64() {
+            return value as i128;
+        }
+        let value = self.as_u128();
+        if Self::MAX_ABS < value {
+            value as i128 - M::MODULUS as i128
+        } else {
+            value as i128
+        }
     }
 
+    /// [`Self::as_i128`] を `i64` に収まるよう飽和させる.
     #[inline]
-    pub unsafe fn from_inner(tuple: (ModInt924844033, ModInt998244353)) -> Self {
-        Self(tuple.0, tuple.1)
+    pub fn as_i64(self) -> i64 {
+        self.small_i64()
+            .unwrap_or_else(|| self.as_i128().clamp(i64::MIN as i128, i64::MAX as i128) as i64)
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> M {
+        self.0
+    }
+
+    /// # Safety
+    ///
+    /// 各剰余は同じ整数を表していなければならない. そうでないと復元した値が意味を持たない.
+    #[inline]
+    pub unsafe fn from_inner(residues: M) -> Self {
+        Self(residues)
+    }
+
+    /// 別の法の組に移す. 移す先で正確に表せない値は意味を持たない.
+    #[inline]
+    pub fn convert<N: Moduli>(self) -> PixelOf<N> {
+        match self.small_i64() {
+            Some(value) => PixelOf::from_signed(value),
+            None => PixelOf::from_i128(self.as_i128()),
+        }
+    }
+
+    /// 長さ `lhs_len` で絶対値 `lhs_max` 以下の列と, 長さ `rhs_len` で絶対値 `rhs_max` 以下の列の畳み込みを正確に表せるか.
+    pub fn fits_convolution(lhs_len: usize, lhs_max: u128, rhs_len: usize, rhs_max: u128) -> bool {
+        lhs_len
+            .min(rhs_len)
+            .try_into()
+            .ok()
+            .and_then(|len: u128| len.checked_mul(lhs_max)?.checked_mul(rhs_max))
+            .is_some_and(|bound| bound <= Self::MAX_ABS)
     }
 
+    /// 符号付き整数とみなして `min..=max` に収める.
+    ///
+    /// 法ごとに収めると, 片方の法の半分を超える値で 2 つの剰余の符号の判断が食い違うので, 復元してから収める.
     #[inline]
     pub fn clamp(self, min: i64, max: i64) -> Self {
-        Self(self.0.clamp(min, max), self.1.clamp(min, max))
+        let value = self.as_i64();
+        if (min..=max).contains(&value) {
+            return self;
+        }
+        Self::from_signed(value.clamp(min, max))
+    }
+
+    /// 符号付き 16 ビット整数として桁あふれさせる.
+    #[inline]
+    pub fn wrap_i16(self) -> Self {
+        Self::from_signed(self.as_i64() as i16 as i64)
+    }
+
+    /// 法ごとに符号付きとみなした値が一致すれば, 中国剰余定理からそれが元の値になる. 復元より安く求まる.
+    #[inline]
+    fn small_i64(self) -> Option<i64> {
+        let signed = |i: usize| {
+            let modulo = M::MODULI[i] as i64;
+            let value = self.0.residue(i) as i64;
+            if modulo / 2 < value {
+                value - modulo
+            } else {
+                value
+            }
+        };
+        let value = signed(0);
+        (1..M::MODULI.len())
+            .all(|i| signed(i) == value)
+            .then_some(value)
     }
 }
 
-impl ops::Add for Pixel {
+/// 列の絶対値の最大値. 空なら 0.
+pub(crate) fn max_abs<M: Moduli>(vec: &[PixelOf<M>]) -> u128 {
+    vec.iter()
+        .map(|px| px.as_i128().unsigned_abs())
+        .max()
+        .unwrap_or(0)
+}
+
+/// 必要な法の数. [`MAX_WIDTH`] 個でも足りなければ `None`.
+pub fn required_width(
+    lhs_len: usize,
+    lhs_max: u128,
+    rhs_len: usize,
+    rhs_max: u128,
+) -> Option<usize> {
+    [
+        (
+            2,
+            Pixel::fits_convolution as fn(usize, u128, usize, u128) -> bool,
+        ),
+        (3, Pixel3::fits_convolution),
+        (4, Pixel4::fits_convolution),
+    ]
+    .into_iter()
+    .find(|(_, fits)| fits(lhs_len, lhs_max, rhs_len, rhs_max))
+    .map(|(width, _)| width)
+}
+
+impl<M: Moduli> ops::Add for PixelOf<M> {
     type Output = Self;
 
     fn add(self, rhs: Self) -> Self::Output {
-        Self(self.0 + rhs.0, self.1 + rhs.1)
+        Self(self.0.add(rhs.0))
     }
 }
 
-impl ops::AddAssign for Pixel {
+impl<M: Moduli> ops::AddAssign for PixelOf<M> {
     fn add_assign(&mut self, rhs: Self) {
-        self.0 = self.0 + rhs.0;
-        self.1 = self.1 + rhs.1;
+        self.0 = self.0.add(rhs.0);
     }
 }
 
-impl ops::Sub for Pixel {
+impl<M: Moduli> ops::Sub for PixelOf<M> {
     type Output = Self;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        Self(self.0 - rhs.0, self.1 - rhs.1)
+        Self(self.0.sub(rhs.0))
     }
 }
 
-impl ops::Mul for Pixel {
+impl<M: Moduli> ops::Mul for PixelOf<M> {
     type Output = Self;
 
     fn mul(self, rhs: Self) -> Self::Output {
-        Self(self.0 * rhs.0, self.1 * rhs.1)
+        Self(self.0.mul(rhs.0))
     }
 }
 
-impl std::iter::Sum for Pixel {
+impl<M: Moduli> std::iter::Sum for PixelOf<M> {
     fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
         use ops::Add;
-        iter.fold(Default::default(), Pixel::add)
+        iter.fold(Default::default(), PixelOf::add)
     }
 }
+
+#[test]
+fn clamp_signed_values() {
+    let clamp = |value: i64| Pixel::from_signed(value).clamp(-32768, 32767).as_i64();
+    assert_eq!(clamp(-1), -1);
+    assert_eq!(clamp(-32768), -32768);
+    assert_eq!(clamp(-32769), -32768);
+    assert_eq!(clamp(-100_000), -32768);
+    assert_eq!(clamp(40_000), 32767);
+    // 片方の法の半分を超える値
+    assert_eq!(clamp(480_000_000), 32767);
+    assert_eq!(clamp(-480_000_000), -32768);
+    assert_eq!(clamp(-4_000_000_000_000), -32768);
+
+    let wrap = |value: i64| Pixel::from_signed(value).wrap_i16().as_i64();
+    assert_eq!(wrap(-5), -5);
+    assert_eq!(wrap(32768), -32768);
+    assert_eq!(wrap(-32769), 32767);
+}
+
+#[test]
+fn garner_reconstructs_wide_values() {
+    fn round_trip<M: Moduli>(value: i128) {
+        assert_eq!(PixelOf::<M>::from_i128(value).as_i128(), value);
+    }
+    for value in [
+        0,
+        1,
+        -1,
+        123_456_789_012,
+        -(1 << 58),
+        Pixel::MAX_ABS as i128,
+    ] {
+        round_trip::<PixelModuli>(value);
+    }
+    for value in [1 << 70, -(3 << 80), Pixel3::MAX_ABS as i128] {
+        round_trip::<Pixel3Moduli>(value);
+    }
+    for value in [1 << 100, -(5 << 110), -(Pixel4::MAX_ABS as i128)] {
+        round_trip::<Pixel4Moduli>(value);
+    }
+    // 2 つの法では収まらず, 積を法とした値に戻る
+    assert_ne!(Pixel::from_i128(1 << 70).as_i128(), 1 << 70);
+    assert_eq!(
+        Pixel::from_i128(1 << 70).as_i64(),
+        Pixel::from_i128(1 << 70).as_i128() as i64
+    );
+}
+
+#[test]
+fn required_width_of_convolution() {
+    let sample = i16::MAX as u128 + 1;
+    assert_eq!(required_width(480_000, sample, 480_000, sample), Some(2));
+    // 2 乗した値どうしの畳み込みは 2 つの法に収まらない
+    let squared = sample * sample;
+    assert_eq!(required_width(480_000, squared, 480_000, squared), Some(3));
+    assert_eq!(required_width(1 << 40, 1 << 40, 1 << 40, 1 << 40), None);
+}
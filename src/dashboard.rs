@@ -0,0 +1,252 @@
+//! 試合中に問題の状況と解の候補を表示し, 手動で解答を調整して送信するための端末画面.
+//!
+//! ログが画面を崩すので, 標準エラー出力はファイルなどに向けて起動すること.
+
+use std::time::{Duration, SystemTime};
+
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Style, Stylize},
+    text::Line,
+    widgets::{Block, Paragraph, Row, Table, TableState},
+    DefaultTerminal, Frame,
+};
+
+use procon2022_comp_2nd::{
+    audio_vec::owned::Owned,
+    request::{Answer, Problem, Requester},
+    solve::{card_voice::CardVoiceIndex, InspectPoint, Loss, Validation},
+};
+
+struct Dashboard<'a, R> {
+    loss: &'a Loss,
+    requester: &'a R,
+    problem: Problem,
+    /// 取得した分割データの数
+    using_chunks: u8,
+    /// 取得した分割データをつなげた音声
+    voice: Owned,
+    /// まだ評価していない読み札
+    pending: Vec<CardVoiceIndex>,
+    /// 評価済みの読み札を損失の小さい順に並べたもの
+    points: Vec<InspectPoint>,
+    /// 解答の候補
+    selected: Vec<InspectPoint>,
+    validation: Option<Validation>,
+    table: TableState,
+    status: String,
+}
+
+impl<'a, R: Requester> Dashboard<'a, R> {
+    fn chunk(&self) -> &Owned {
+        &self.voice
+    }
+
+    /// 読み札を 1 つ評価して表に加える. すべて評価し終えたら, 上位の札を解答の候補にする.
+    fn evaluate_next(&mut self) {
+        let Some(index) = self.pending.pop() else {
+            return;
+        };
+        let point = self.loss.evaluate(self.chunk(), index);
+        let at = self.points.partition_point(|p| p.score <= point.score);
+        self.points.insert(at, point);
+        if self.pending.is_empty() {
+            self.reset_selection();
+        }
+    }
+
+    fn reset_selection(&mut self) {
+        let solutions = (self.problem.data as usize).min(self.points.len());
+        self.selected = self.points[..solutions].to_vec();
+        self.revalidate();
+    }
+
+    /// カーソルの位置の読み札を解答の候補に入れる, または候補から外す.
+    fn toggle(&mut self) {
+        let Some(&point) = self.table.selected().and_then(|i| self.points.get(i)) else {
+            return;
+        };
+        if let Some(at) = self
+            .selected
+            .iter()
+            .position(|p| p.using_voice == point.using_voice)
+        {
+            self.selected.remove(at);
+        } else {
+            self.selected.push(point);
+        }
+        self.revalidate();
+    }
+
+    fn revalidate(&mut self) {
+        self.validation =
+            (!self.selected.is_empty()).then(|| self.loss.validate(self.chunk(), &self.selected));
+    }
+
+    fn post(&mut self) {
+        let answer = Answer {
+            problem_id: self.problem.id.clone(),
+            answers: self
+                .selected
+                .iter()
+                .map(|p| self.loss.library().answer(p.using_voice).to_owned())
+                .collect(),
+        };
+        self.status = match self.requester.post_answer(&answer) {
+            Ok(response) => format!("posted: {response:?}"),
+            Err(err) => format!("post failed: {err}"),
+        };
+    }
+
+    fn time_remaining(&self) -> String {
+        match self.problem.deadline().duration_since(SystemTime::now()) {
+            Ok(remaining) => {
+                let secs = remaining.as_secs();
+                format!("{}:{:02}", secs / 60, secs % 60)
+            }
+            Err(_) => "expired".into(),
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [header, table, footer] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(0),
+            Constraint::Length(5),
+        ])
+        .areas(frame.area());
+
+        let library = self.loss.library();
+        let header_text = Line::from(format!(
+            "problem: {}  chunks: {}/{}  cards: {}  remaining: {}",
+            self.problem.id,
+            self.using_chunks,
+            self.problem.chunks,
+            self.problem.data,
+            self.time_remaining(),
+        ));
+        frame.render_widget(
+            Paragraph::new(header_text).block(Block::bordered().title("match")),
+            header,
+        );
+
+        let rows = self.points.iter().enumerate().map(|(rank, point)| {
+            let selected = self
+                .selected
+                .iter()
+                .any(|p| p.using_voice == point.using_voice);
+            let row = Row::new([
+                if selected { "*" } else { "" }.to_owned(),
+                (rank + 1).to_string(),
+                library.label(point.using_voice).to_owned(),
+                library.answer(point.using_voice).to_owned(),
+                point.delay.to_string(),
+                point.score.to_string(),
+            ]);
+            if selected {
+                row.bold()
+            } else {
+                row
+            }
+        });
+        let widths = [
+            Constraint::Length(1),
+            Constraint::Length(4),
+            Constraint::Length(8),
+            Constraint::Length(6),
+            Constraint::Length(10),
+            Constraint::Min(20),
+        ];
+        let title = if self.pending.is_empty() {
+            "points".to_owned()
+        } else {
+            format!("points (evaluating, {} left)", self.pending.len())
+        };
+        let points_table = Table::new(rows, widths)
+            .header(Row::new(["", "rank", "card", "answer", "delay", "score"]).underlined())
+            .block(Block::bordered().title(title))
+            .row_highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(points_table, table, &mut self.table);
+
+        let selection = self
+            .selected
+            .iter()
+            .map(|p| format!("{}@{}", library.label(p.using_voice), p.delay))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let validation = match self.validation {
+            Some(validation) if validation.is_valid() => format!("{} (valid)", validation.score),
+            Some(validation) => format!("{} (invalid)", validation.score),
+            None => "-".into(),
+        };
+        let footer_text = vec![
+            Line::from(format!("candidate: {selection}")),
+            Line::from(format!("residual: {validation}")),
+            Line::from(self.status.clone()),
+        ];
+        frame.render_widget(
+            Paragraph::new(footer_text).block(
+                Block::bordered()
+                    .title("answer")
+                    .title_bottom("↑↓: move  space: swap in/out  r: reset  p: post  q: quit"),
+            ),
+            footer,
+        );
+    }
+
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> anyhow::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let timeout = if self.pending.is_empty() {
+                Duration::from_millis(250)
+            } else {
+                Duration::ZERO
+            };
+            if !event::poll(timeout)? {
+                self.evaluate_next();
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Up | KeyCode::Char('k') => self.table.select_previous(),
+                KeyCode::Down | KeyCode::Char('j') => self.table.select_next(),
+                KeyCode::Char(' ') | KeyCode::Enter => self.toggle(),
+                KeyCode::Char('r') => self.reset_selection(),
+                KeyCode::Char('p') => self.post(),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// 問題を取得し, 端末画面で解答を確認しながら送信する.
+pub fn run(loss: &Loss, requester: &impl Requester) -> anyhow::Result<()> {
+    let problem = requester.get_problem()?;
+    let using_chunks = 1;
+    let voice = requester.get_problem_voice(using_chunks)?;
+    let mut dashboard = Dashboard {
+        loss,
+        requester,
+        problem,
+        using_chunks,
+        voice,
+        pending: loss.library().all().collect(),
+        points: vec![],
+        selected: vec![],
+        validation: None,
+        table: TableState::default().with_selected(0),
+        status: String::new(),
+    };
+
+    let mut terminal = ratatui::try_init()?;
+    let result = dashboard.event_loop(&mut terminal);
+    ratatui::try_restore()?;
+    result
+}
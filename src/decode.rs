@@ -0,0 +1,283 @@
+use std::io;
+
+use thiserror::Error;
+
+use crate::audio_vec::owned::Owned;
+
+/// 読み札の音声のサンプリング周波数. 問題の音声もこれに揃える.
+pub const SAMPLE_RATE: u32 = 48000;
+
+const FORMAT_PCM: u16 = 0x0001;
+const FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("not a RIFF WAVE file")]
+    NotWave,
+    #[error("{0} chunk not found")]
+    MissingChunk(&'static str),
+    #[error("unsupported format: tag {format_tag:#06x} with {bits_per_sample} bits per sample")]
+    UnsupportedFormat {
+        format_tag: u16,
+        bits_per_sample: u16,
+    },
+    #[error("the number of channels is zero")]
+    NoChannel,
+    #[error("the sample rate is zero")]
+    NoSampleRate,
+    #[error("sample rate {actual} Hz does not match {expected} Hz")]
+    SampleRateMismatch { expected: u32, actual: u32 },
+}
+
+/// サンプリング周波数が [`SAMPLE_RATE`] と異なる場合の扱い.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleRatePolicy {
+    /// エラーとする.
+    Check,
+    /// 線形補間で [`SAMPLE_RATE`] に変換する.
+    Resample,
+}
+
+/// モノラルに変換した 16-bit の音声.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pcm {
+    pub sample_rate: u32,
+    pub samples: Vec<i16>,
+}
+
+impl Pcm {
+    pub fn into_owned(self, policy: SampleRatePolicy) -> Result<Owned, DecodeError> {
+        if self.sample_rate == SAMPLE_RATE {
+            return Ok(Owned::from_pcm(&self.samples));
+        }
+        match policy {
+            SampleRatePolicy::Check => Err(DecodeError::SampleRateMismatch {
+                expected: SAMPLE_RATE,
+                actual: self.sample_rate,
+            }),
+            SampleRatePolicy::Resample => Ok(Owned::from_pcm(&resample(
+                &self.samples,
+                self.sample_rate,
+                SAMPLE_RATE,
+            ))),
+        }
+    }
+}
+
+/// WAV ファイルのバイト列を音声データに変換する. サンプリング周波数が異なる場合は変換する.
+pub fn decode_wav(bytes: &[u8]) -> Result<Owned, DecodeError> {
+    read_pcm(bytes)?.into_owned(SampleRatePolicy::Resample)
+}
+
+/// WAV ファイルを読み込み, 8/16/24/32-bit の整数または 32/64-bit の浮動小数点数の PCM を 16-bit のモノラルに変換する.
+pub fn read_pcm(bytes: &[u8]) -> Result<Pcm, DecodeError> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(DecodeError::NotWave);
+    }
+
+    let mut format = None;
+    let mut data = None;
+    let mut rest = &bytes[12..];
+    while 8 <= rest.len() {
+        let id = &rest[0..4];
+        let size = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+        let body = rest
+            .get(8..8 + size)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        match id {
+            b"fmt " => format = Some(Format::parse(body)?),
+            b"data" => data = Some(body),
+            _ => {}
+        }
+        // チャンクは 2 バイト境界に揃えられている
+        rest = rest.get(8 + size + size % 2..).unwrap_or_default();
+    }
+    let format = format.ok_or(DecodeError::MissingChunk("fmt"))?;
+    let data = data.ok_or(DecodeError::MissingChunk("data"))?;
+
+    let channels = format.channels as usize;
+    if channels == 0 {
+        return Err(DecodeError::NoChannel);
+    }
+    // 0 のままだとサンプリング周波数の変換で 0 で割ることになる
+    if format.sample_rate == 0 {
+        return Err(DecodeError::NoSampleRate);
+    }
+    let bytes_per_sample = (format.bits_per_sample as usize).div_ceil(8);
+    let decode_sample = format.sample_decoder()?;
+    let samples = data
+        .chunks_exact(bytes_per_sample * channels)
+        .map(|frame| {
+            let sum: f64 = frame
+                .chunks_exact(bytes_per_sample)
+                .map(decode_sample)
+                .sum();
+            to_i16(sum / channels as f64)
+        })
+        .collect();
+    Ok(Pcm {
+        sample_rate: format.sample_rate,
+        samples,
+    })
+}
+
+#[derive(Debug)]
+struct Format {
+    format_tag: u16,
+    channels: u16,
+    sample_rate: u32,
+    bits_per_sample: u16,
+}
+
+impl Format {
+    fn parse(body: &[u8]) -> Result<Self, DecodeError> {
+        if body.len() < 16 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let mut format_tag = u16::from_le_bytes([body[0], body[1]]);
+        if format_tag == FORMAT_EXTENSIBLE && 26 <= body.len() {
+            // WAVE_FORMAT_EXTENSIBLE では SubFormat の GUID の先頭 2 バイトが実際の形式を表す
+            format_tag = u16::from_le_bytes([body[24], body[25]]);
+        }
+        Ok(Self {
+            format_tag,
+            channels: u16::from_le_bytes([body[2], body[3]]),
+            sample_rate: u32::from_le_bytes([body[4], body[5], body[6], body[7]]),
+            bits_per_sample: u16::from_le_bytes([body[14], body[15]]),
+        })
+    }
+
+    /// 1 サンプル分のバイト列を 16-bit のスケールの値に変換する関数を選ぶ.
+    fn sample_decoder(&self) -> Result<fn(&[u8]) -> f64, DecodeError> {
+        Ok(match (self.format_tag, self.bits_per_sample) {
+            (FORMAT_PCM, 8) => |b| (b[0] as f64 - 128.0) * 256.0,
+            (FORMAT_PCM, 16) => |b| i16::from_le_bytes([b[0], b[1]]) as f64,
+            (FORMAT_PCM, 24) => |b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f64 / 256.0,
+            (FORMAT_PCM, 32) => |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64 / 65536.0,
+            (FORMAT_IEEE_FLOAT, 32) => {
+                |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64 * 32768.0
+            }
+            (FORMAT_IEEE_FLOAT, 64) => {
+                |b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) * 32768.0
+            }
+            (format_tag, bits_per_sample) => {
+                return Err(DecodeError::UnsupportedFormat {
+                    format_tag,
+                    bits_per_sample,
+                })
+            }
+        })
+    }
+}
+
+fn to_i16(value: f64) -> i16 {
+    value.round().clamp(i16::MIN as f64, i16::MAX as f64) as i16
+}
+
+/// 線形補間でサンプリング周波数を `from` から `to` に変換する.
+fn resample(samples: &[i16], from: u32, to: u32) -> Vec<i16> {
+    if samples.is_empty() {
+        return vec![];
+    }
+    let len = (samples.len() as u64 * to as u64 / from as u64) as usize;
+    (0..len)
+        .map(|i| {
+            let pos = i as f64 * from as f64 / to as f64;
+            let left = pos.floor() as usize;
+            let frac = pos - left as f64;
+            let a = samples[left] as f64;
+            let b = samples.get(left + 1).copied().unwrap_or(samples[left]) as f64;
+            to_i16(a + (b - a) * frac)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+fn encode_test_wav(
+    format_tag: u16,
+    channels: u16,
+    sample_rate: u32,
+    bits: u16,
+    data: &[u8],
+) -> Vec<u8> {
+    let mut bytes = vec![];
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&format_tag.to_le_bytes());
+    bytes.extend_from_slice(&channels.to_le_bytes());
+    bytes.extend_from_slice(&sample_rate.to_le_bytes());
+    let block_align = channels * bits / 8;
+    bytes.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    bytes.extend_from_slice(&block_align.to_le_bytes());
+    bytes.extend_from_slice(&bits.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(data);
+    bytes
+}
+
+#[test]
+fn decode_bit_depths() -> Result<(), DecodeError> {
+    let expected = vec![-256, 0, 512];
+
+    let eight = encode_test_wav(FORMAT_PCM, 1, SAMPLE_RATE, 8, &[127, 128, 130]);
+    assert_eq!(read_pcm(&eight)?.samples, expected);
+
+    let twenty_four: Vec<u8> = [-256i32 * 256, 0, 512 * 256]
+        .into_iter()
+        .flat_map(|x| x.to_le_bytes()[..3].to_vec())
+        .collect();
+    let twenty_four = encode_test_wav(FORMAT_PCM, 1, SAMPLE_RATE, 24, &twenty_four);
+    assert_eq!(read_pcm(&twenty_four)?.samples, expected);
+
+    let thirty_two: Vec<u8> = [-256i32 * 65536, 0, 512 * 65536]
+        .into_iter()
+        .flat_map(i32::to_le_bytes)
+        .collect();
+    let thirty_two = encode_test_wav(FORMAT_PCM, 1, SAMPLE_RATE, 32, &thirty_two);
+    assert_eq!(read_pcm(&thirty_two)?.samples, expected);
+
+    let float: Vec<u8> = [-256.0f32 / 32768.0, 0.0, 512.0 / 32768.0]
+        .into_iter()
+        .flat_map(f32::to_le_bytes)
+        .collect();
+    let float = encode_test_wav(FORMAT_IEEE_FLOAT, 1, SAMPLE_RATE, 32, &float);
+    assert_eq!(read_pcm(&float)?.samples, expected);
+
+    Ok(())
+}
+
+#[test]
+fn decode_stereo_and_sample_rate() -> Result<(), DecodeError> {
+    let stereo: Vec<u8> = [100i16, 300, -50, -150, 7, 7]
+        .into_iter()
+        .flat_map(i16::to_le_bytes)
+        .collect();
+    let stereo = encode_test_wav(FORMAT_PCM, 2, 24000, 16, &stereo);
+    let pcm = read_pcm(&stereo)?;
+    assert_eq!(pcm.samples, vec![200, -100, 7]);
+
+    assert!(matches!(
+        pcm.clone().into_owned(SampleRatePolicy::Check),
+        Err(DecodeError::SampleRateMismatch {
+            expected: SAMPLE_RATE,
+            actual: 24000
+        })
+    ));
+    let resampled = pcm.into_owned(SampleRatePolicy::Resample)?;
+    assert_eq!(resampled, Owned::from_pcm(&[200, 50, -100, -47, 7, 7]));
+
+    Ok(())
+}
+
+#[test]
+fn reject_zero_sample_rate() {
+    let wav = encode_test_wav(FORMAT_PCM, 1, 0, 16, &[1, 0, 2, 0]);
+    assert!(matches!(read_pcm(&wav), Err(DecodeError::NoSampleRate)));
+    assert!(matches!(decode_wav(&wav), Err(DecodeError::NoSampleRate)));
+}
@@ -0,0 +1,158 @@
+use std::{fs, fs::File, path::Path};
+
+use anyhow::{bail, Context};
+use log::info;
+
+use procon2022_comp_2nd::{
+    audio_vec::{owned::Owned, AudioVec},
+    decode::{decode_wav, SAMPLE_RATE},
+    solve::{card_voice::CardLibrary, InspectPoint, Loss},
+};
+
+/// `comparison.wav` で問題, 再構成, 残差の間に挟む無音の長さ. 0.5 秒分.
+const COMPARISON_GAP: usize = SAMPLE_RATE as usize / 2;
+/// `comparison.wav` で残差を増幅する倍率.
+const RESIDUAL_GAIN: i64 = 4;
+
+/// 16-bit モノラルの WAV ファイルとして書き出す.
+pub fn write_wav(path: &Path, voice: &Owned) -> anyhow::Result<()> {
+    let header = wav::Header::new(wav::WAV_FORMAT_PCM, 1, SAMPLE_RATE, 16);
+    let data = wav::BitDepth::Sixteen(voice.to_pcm());
+    wav::write(header, &data, &mut File::create(path)?)?;
+    info!("exported: {}", path.display());
+    Ok(())
+}
+
+/// `answer` を [`Loss::validate`] と同じように重ね合わせ, その再構成, 問題との残差, 遅らせた読み札それぞれと,
+/// それらを続けて聞き比べるための `comparison.wav` を `dir` に書き出す.
+pub fn export_answer(
+    loss: &Loss,
+    problem_voice: &Owned,
+    answer: &[InspectPoint],
+    dir: &Path,
+) -> anyhow::Result<()> {
+    fs::create_dir_all(dir)?;
+    let len = problem_voice.len();
+    for point in answer {
+        write_wav(
+            &dir.join(format!(
+                "card_{}_{}.wav",
+                loss.library().label(point.using_voice),
+                point.delay
+            )),
+            &loss.delayed_card(point, len),
+        )?;
+    }
+    let reconstruction = loss.mix(len, answer);
+    let residual = loss.residual(problem_voice, answer);
+    write_wav(&dir.join("reconstruction.wav"), &reconstruction)?;
+    write_wav(&dir.join("residual.wav"), &residual)?;
+
+    // 問題, 再構成, 聞き取りやすく増幅した残差を, 間を空けて続けて聞けるようにつなげる
+    let part_len = len + COMPARISON_GAP;
+    let comparison = problem_voice
+        .window(0, len)
+        .concat(
+            part_len,
+            (&reconstruction)
+                .window(0, len)
+                .concat(part_len, (&residual).scale(RESIDUAL_GAIN).clip(len)),
+        )
+        .to_owned(3 * part_len);
+    write_wav(&dir.join("comparison.wav"), &comparison)?;
+    Ok(())
+}
+
+/// `E01@4800` のように, 読み札と遅延を `@` で繋いだものを読む.
+fn parse_point(library: &CardLibrary, arg: &str) -> anyhow::Result<InspectPoint> {
+    let (card, delay) = arg
+        .split_once('@')
+        .with_context(|| format!("expected <card>@<delay> but got {arg}"))?;
+    Ok(InspectPoint {
+        using_voice: library
+            .find(card)
+            .with_context(|| format!("unknown card: {card}"))?,
+        delay: delay.parse()?,
+        score: 0,
+    })
+}
+
+/// `export <problem.wav> <out_dir> <card>@<delay>...` を実行する.
+pub fn run(loss: &Loss, args: &[String]) -> anyhow::Result<()> {
+    let [problem, dir, points @ ..] = args else {
+        bail!("usage: export <problem.wav> <out_dir> <card>@<delay>...");
+    };
+    let problem_voice = decode_wav(&fs::read(problem)?)?;
+    let answer = points
+        .iter()
+        .map(|arg| parse_point(loss.library(), arg))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    export_answer(loss, &problem_voice, &answer, Path::new(dir))
+}
+
+#[test]
+fn export_residual_is_problem_minus_mix() -> anyhow::Result<()> {
+    use std::collections::HashMap;
+
+    use procon2022_comp_2nd::solve::card_voice::{Card, Language};
+
+    let library = CardLibrary::new(
+        (0..2)
+            .map(|i| Card {
+                path: format!("{i}.wav").into(),
+                label: format!("C{i}"),
+                answer: i.to_string(),
+                language: Language::En,
+            })
+            .collect(),
+    )?;
+    let card_voices: HashMap<_, _> = library
+        .all()
+        .enumerate()
+        .map(|(i, index)| {
+            let pcm: Vec<_> = (0..600)
+                .map(|t| ((t * (7 + i as i64) % 97) * 20 - 960) as i16)
+                .collect();
+            (index, Owned::from_pcm(&pcm))
+        })
+        .collect();
+    let loss = Loss::new(library, card_voices);
+    let answer: Vec<_> = [("C0", -100), ("C1", 250)]
+        .into_iter()
+        .map(|(label, delay)| InspectPoint {
+            using_voice: loss.library().find(label).unwrap(),
+            delay,
+            score: 0,
+        })
+        .collect();
+
+    // 読み札の重ね合わせに, 答えでは説明できない音を加えた問題
+    let len = 1000;
+    let noise: Vec<_> = (0..len as i64)
+        .map(|t| ((t * 31 % 53) * 10 - 260) as i16)
+        .collect();
+    let problem_voice = loss
+        .mix(len, &answer)
+        .add(Owned::from_pcm(&noise))
+        .to_owned(len);
+
+    let dir = std::env::temp_dir().join(format!("export_answer_{}", std::process::id()));
+    export_answer(&loss, &problem_voice, &answer, &dir)?;
+    let read = |name: &str| -> anyhow::Result<Vec<i16>> {
+        Ok(decode_wav(&fs::read(dir.join(name))?)?.to_pcm())
+    };
+    let reconstruction = read("reconstruction.wav")?;
+    let residual = read("residual.wav")?;
+    fs::remove_dir_all(&dir)?;
+
+    assert_eq!(reconstruction, loss.mix(len, &answer).to_pcm());
+    let problem = problem_voice.to_pcm();
+    let expected: Vec<_> = problem
+        .iter()
+        .zip(&reconstruction)
+        .map(|(&p, &r)| p - r)
+        .collect();
+    assert_eq!(residual, expected);
+    assert_eq!(residual, noise);
+    Ok(())
+}
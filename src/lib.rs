@@ -0,0 +1,7 @@
+//! 読み札の音声を分離する処理. 実行ファイルのほか, ベンチマークからも使う.
+
+pub mod audio_vec;
+pub mod decode;
+pub mod precalc;
+pub mod request;
+pub mod solve;
@@ -1,88 +1,468 @@
-use log::info;
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::{
-    precalc::load_all_jk,
-    request::{mock::MockRequester, net::NetRequester, Answer},
-    solve::Loss,
+use anyhow::anyhow;
+use log::{info, warn};
+
+use procon2022_comp_2nd::{
+    audio_vec::owned::Owned,
+    precalc::load_card_voices,
+    request::{
+        mock::MockRequester, net::NetRequester, record::RecordRequester, replay::ReplayRequester,
+        Answer, Problem, Requester,
+    },
+    solve::{
+        branch_bound::BranchAndBound, card_voice::CardLibrary, constraint::Constraints,
+        mixing::Mixing, scoring::Scoring, InspectPoint, Loss, Validation,
+    },
+};
+
+use self::{
+    manual::ManualInput,
+    report::{Report, Timings},
 };
 
-use self::request::Requester;
+mod dashboard;
+mod export;
+mod manual;
+mod report;
+mod score_command;
 
-mod audio_vec;
-mod precalc;
-mod request;
-mod solve;
+/// 環境変数 `TOP_DELAYS` が無いときに, 読み札ごとに試す遅延の候補の数.
+const DEFAULT_TOP_DELAYS: usize = 3;
+/// 環境変数 `REFINE_ROUNDS` が無いときに, 最初の解や分枝限定法で見つけた解の遅延を合わせ直す回数.
+const DEFAULT_REFINE_ROUNDS: usize = 2;
+/// 分枝限定法で組み合わせを探す候補の数.
+const BRANCH_AND_BOUND_CANDIDATES: usize = 40;
+/// 問題の制限時間のうち, 解答の送信のために残しておく時間.
+const POST_MARGIN: Duration = Duration::from_secs(2);
+/// 読み札の枚数を推定するときの上限. 競技の問題は最大 20 枚.
+const MAX_ESTIMATED_COUNT: usize = 20;
 
 fn main() -> anyhow::Result<()> {
     dotenv::dotenv()?;
     env_logger::init();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("export") {
+        return export::run(&load_loss()?, &args[1..]);
+    }
+    if args.first().map(String::as_str) == Some("scoring") {
+        return score_command::run(load_loss()?, &args[1..]);
+    }
+
     let endpoint = std::env::var("ENDPOINT")?;
     let token = std::env::var("TOKEN")?;
     let debug = std::env::var("DEBUG")?;
 
-    let all_jk = load_all_jk()?;
-    let loss = Loss::new(all_jk);
+    let loss = load_loss()?;
 
     info!("setup complete");
 
+    if let Ok(replay_dir) = std::env::var("REPLAY_DIR") {
+        let requester = ReplayRequester::new(replay_dir.into());
+        return run(loss, &requester, &args);
+    }
+
     if debug.as_str().trim() == "True" {
         let requester =
             MockRequester::new(["assets", "sample", "sample_Q_E01"].into_iter().collect());
-        run_solver(loss, &requester)
+        run(loss, &requester, &args)
     } else {
         let requester = NetRequester::new(&endpoint, &token);
-        run_solver(loss, &requester)
+        if let Ok(record_dir) = std::env::var("RECORD_DIR") {
+            let requester = RecordRequester::new(requester, record_dir.into())?;
+            run(loss, &requester, &args)
+        } else {
+            run(loss, &requester, &args)
+        }
     }
 }
 
-fn run_solver(loss: Loss, requester: &impl Requester) -> anyhow::Result<()> {
+fn load_loss() -> anyhow::Result<Loss> {
+    let library = CardLibrary::from_env()?;
+    let card_voices = load_card_voices(&library)?;
+    let mut loss = Loss::new(library, card_voices)
+        .with_scoring(Scoring::from_env()?)
+        .with_mixing(Mixing::from_env()?);
+    // 環境変数 `COARSE_FACTOR` があれば, その分の 1 に間引いた音声で先に候補を絞り込む
+    if let Ok(factor) = std::env::var("COARSE_FACTOR") {
+        let factor: usize = factor.parse()?;
+        if factor == 0 {
+            return Err(anyhow!("COARSE_FACTOR must be at least 1"));
+        }
+        loss = loss.with_coarse(factor);
+    }
+    // 環境変数 `SPECTROGRAM_WEIGHT` があれば, 候補の並べ替えにスペクトログラムの照合も使う
+    Ok(match std::env::var("SPECTROGRAM_WEIGHT") {
+        Ok(weight) => loss.with_spectrogram(weight.parse()?),
+        Err(_) => loss,
+    })
+}
+
+fn run(loss: Loss, requester: &impl Requester, args: &[String]) -> anyhow::Result<()> {
+    if args.first().map(String::as_str) == Some("dashboard") {
+        dashboard::run(&loss, requester)
+    } else {
+        run_solver(loss, requester, parse_budget(args)?)
+    }
+}
+
+/// `--budget <秒>` で探索にかける時間を与える.
+fn parse_budget(args: &[String]) -> anyhow::Result<Option<Duration>> {
+    let Some(at) = args.iter().position(|arg| arg == "--budget") else {
+        return Ok(None);
+    };
+    let secs: f64 = args
+        .get(at + 1)
+        .ok_or_else(|| anyhow!("--budget needs seconds"))?
+        .parse()?;
+    // 負の数, NaN, 無限大は `Duration` にならない
+    let budget = Duration::try_from_secs_f64(secs)
+        .ok()
+        .filter(|budget| !budget.is_zero())
+        .ok_or_else(|| anyhow!("--budget must be a positive number of seconds: {secs}"))?;
+    Ok(Some(budget))
+}
+
+/// 探索を打ち切る時刻. 問題の制限時間から送信の余裕を引いたものと, `budget` のうち早い方.
+///
+/// モックや記録の再生では締め切りがもう過ぎているので, そのときは `budget` だけを使う.
+fn solver_deadline(
+    problem: &Problem,
+    started: Instant,
+    budget: Option<Duration>,
+) -> Option<Instant> {
+    let problem_deadline = match problem.deadline().duration_since(SystemTime::now()) {
+        Ok(remaining) => Some(Instant::now() + remaining.saturating_sub(POST_MARGIN)),
+        Err(_) => {
+            warn!("the problem deadline has already passed, ignoring it");
+            None
+        }
+    };
+    problem_deadline
+        .into_iter()
+        .chain(budget.map(|budget| started + budget))
+        .min()
+}
+
+fn run_solver(
+    loss: Loss,
+    requester: &impl Requester,
+    budget: Option<Duration>,
+) -> anyhow::Result<()> {
+    let started = Instant::now();
+    let mut manual = ManualInput::from_env()?;
+    let match_info = requester.get_match()?;
+
+    info!("got match: {:?}", match_info);
+
     let problem_info = requester.get_problem()?;
 
     info!("got problem: {:?}", problem_info);
 
-    let chunks = requester.get_chunks(1)?;
-    let chunk = &chunks[0];
+    let mut report = Report::new(problem_info.clone());
+    // 候補を求めるところから期限を守る
+    let options = SearchOptions {
+        deadline: solver_deadline(&problem_info, started, budget),
+        ..SearchOptions::from_env()?
+    };
+
+    // 環境変数 `SELECTED_CHUNKS` (`1,3` のように 1 から数えた番号) があれば, その分割データだけを問題の中の位置に置く.
+    // 無ければ `USING_CHUNKS` の数だけ先頭から分割データを取得する. それも無ければ 1 つ.
+    let problem_voice = match std::env::var("SELECTED_CHUNKS") {
+        Ok(selection) => {
+            let selection = selection
+                .split(',')
+                .map(|number| number.trim().parse())
+                .collect::<Result<Vec<u8>, _>>()?;
+            requester.get_selected_problem_voice(&selection)?
+        }
+        Err(_) => {
+            let using_chunks = std::env::var("USING_CHUNKS").map_or(Ok(1), |s| s.parse())?;
+            requester.get_problem_voice(using_chunks)?
+        }
+    };
+    let chunk = &problem_voice;
+    report.timings.fetch_ms = Timings::millis(started.elapsed());
 
-    let solutions = problem_info.data as usize;
-    let points_by_loss = loss.find_points(chunk);
+    let find_points_started = Instant::now();
+    let points_by_loss = loss.find_points_until(chunk, top_delays()?, options.deadline);
+    report.timings.find_points_ms = Timings::millis(find_points_started.elapsed());
+    report.set_points(loss.library(), &points_by_loss);
+    if options.expired() {
+        warn!("search deadline passed while finding points");
+    }
 
-    let first_answer = &points_by_loss[..solutions];
+    // 問題の枚数が正しいかを残差から確かめる. `ESTIMATE_COUNT` があれば推定した枚数で解く.
+    let mut data = problem_info.data as usize;
+    if !options.expired() {
+        let estimated_count = loss.estimate_count(chunk, &points_by_loss, MAX_ESTIMATED_COUNT);
+        report.estimated_count = Some(estimated_count);
+        if estimated_count != data {
+            warn!("problem says {data} cards but the residual suggests {estimated_count}");
+        }
+        if estimate_count()? {
+            data = estimated_count;
+        }
+    }
+
+    let search_started = Instant::now();
+    let found = loop {
+        let constraints = match &mut manual {
+            Some(manual) => {
+                manual.poll(loss.library());
+                manual.constraints().clone()
+            }
+            None => Constraints::default(),
+        };
+        let search = search_answer(
+            &loss,
+            chunk,
+            &points_by_loss,
+            &constraints,
+            data,
+            options,
+            manual.as_mut(),
+            &mut report,
+        );
+        // 探索中に制約が変わったので, 新しい制約で探し直す
+        if search.interrupted {
+            continue;
+        }
+        let found = search.best;
+        let Some(manual) = &mut manual else {
+            break found;
+        };
+        if options.expired() {
+            break found;
+        }
+        if manual.poll(loss.library()) {
+            continue;
+        }
+        if found
+            .as_ref()
+            .is_some_and(|best| best.validation.is_valid())
+        {
+            break found;
+        }
+        warn!("no valid answer under {constraints:?}, waiting for manual input");
+        if !manual.wait(loss.library(), options.deadline) {
+            break found;
+        }
+    };
+    report.timings.search_ms = Timings::millis(search_started.elapsed());
+
+    report.timed_out = options.expired();
+    // 誤答は減点されるので, 検算に合わない解は送らない
+    let result = match found {
+        Some(found) if found.validation.is_valid() => {
+            let post_started = Instant::now();
+            let answer = Answer {
+                problem_id: problem_info.id,
+                answers: found
+                    .answer
+                    .iter()
+                    .map(|p| loss.library().answer(p.using_voice).to_owned())
+                    .collect(),
+            };
+            report.posted = Some(answer.clone());
+            let response = requester.post_answer(&answer);
+            report.timings.post_ms = Timings::millis(post_started.elapsed());
+            response.map(|response| report.response = Some(response))
+        }
+        Some(found) => {
+            warn!(
+                "no valid answer found, the best one has residual {}",
+                found.validation.score
+            );
+            Err(anyhow!("no valid answer found"))
+        }
+        None => Err(anyhow!("no answer found")),
+    };
+    report.timings.total_ms = Timings::millis(started.elapsed());
+    // 解答はもう送ったので, 記録の書き出しに失敗しても実行の結果は変えない
+    if let Err(err) = report.write(&Report::dir_from_env()) {
+        warn!("failed to write report: {err}");
+    }
+    result
+}
+
+/// 環境変数 `TOP_DELAYS` で, 読み札ごとに試す遅延の候補の数を決める. 1 以上であること.
+fn top_delays() -> anyhow::Result<usize> {
+    let top_delays = std::env::var("TOP_DELAYS").map_or(Ok(DEFAULT_TOP_DELAYS), |s| s.parse())?;
+    if top_delays == 0 {
+        return Err(anyhow!("TOP_DELAYS must be at least 1"));
+    }
+    Ok(top_delays)
+}
+
+/// 環境変数 `ESTIMATE_COUNT` が `true` または `1` なら, 問題の枚数の代わりに推定した枚数で解く.
+/// `false` か `0` か, 無ければ問題の枚数で解く.
+fn estimate_count() -> anyhow::Result<bool> {
+    match std::env::var("ESTIMATE_COUNT").as_deref() {
+        Err(_) | Ok("false" | "0") => Ok(false),
+        Ok("true" | "1") => Ok(true),
+        Ok(other) => Err(anyhow!("ESTIMATE_COUNT must be true or false: {other}")),
+    }
+}
+
+/// 解の探し方.
+#[derive(Debug, Clone, Copy)]
+struct SearchOptions {
+    /// 最初の解と, 分枝限定法で見つけた解の遅延を検算の前に合わせ直す回数. 0 なら合わせ直さない
+    refine_rounds: usize,
+    /// 1 枚ずつの入れ替えの代わりに分枝限定法で探す
+    branch_and_bound: bool,
+    /// これを過ぎたら探索をやめて, それまでで最良の解を送る
+    deadline: Option<Instant>,
+}
+
+impl SearchOptions {
+    /// 環境変数 `REFINE_ROUNDS` と `SEARCH` (`swap` または `branch_and_bound`) から読む.
+    fn from_env() -> anyhow::Result<Self> {
+        let refine_rounds =
+            std::env::var("REFINE_ROUNDS").map_or(Ok(DEFAULT_REFINE_ROUNDS), |s| s.parse())?;
+        let branch_and_bound = match std::env::var("SEARCH").as_deref() {
+            Err(_) | Ok("swap") => false,
+            Ok("branch_and_bound") => true,
+            Ok(other) => return Err(anyhow!("unknown search: {other}")),
+        };
+        Ok(Self {
+            refine_rounds,
+            branch_and_bound,
+            deadline: None,
+        })
+    }
+
+    fn expired(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| deadline <= Instant::now())
+    }
+}
+
+/// 探索で見つけた, 残差の最も小さい解. 検算に合うとは限らない.
+struct Best {
+    answer: Vec<InspectPoint>,
+    validation: Validation,
+}
+
+/// [`search_answer`] の結果.
+struct Search {
+    best: Option<Best>,
+    /// 探索中に `manual` の制約が変わったので打ち切った
+    interrupted: bool,
+}
+
+/// 制約 `constraints` のもとで問題に一致する解を探す. 検算した候補はすべて `report` に記録する.
+///
+/// 期限があれば, 検算に合う解が見つかっても期限まで探し続け, それまでで最も残差の小さい解を返す.
+/// 期限が無ければ, 検算に合う解が見つかったところで止める. どちらも候補を試し尽くしたら止める.
+/// 候補を検算するたびに `manual` の入力を確かめ, 制約が変わっていたら打ち切る.
+///
+/// 合わせ直しは候補ごとに読み札の枚数だけ畳み込むので, 最初の解と分枝限定法で見つけた解にだけ行う.
+/// 入れ替えの候補は合わせ直した最初の解から 1 枚だけ変えたもので, そのまま検算する.
+/// 合わせ直すと, 同じ札の別の遅延に入れ替えた候補も元の遅延に戻ってしまう.
+#[allow(clippy::too_many_arguments)]
+fn search_answer(
+    loss: &Loss,
+    chunk: &Owned,
+    points_by_loss: &[InspectPoint],
+    constraints: &Constraints,
+    data: usize,
+    options: SearchOptions,
+    mut manual: Option<&mut ManualInput>,
+    report: &mut Report,
+) -> Search {
+    let mut best: Option<Best> = None;
+    let mut interrupted = false;
+    let refine = |answer: &[InspectPoint]| {
+        if options.refine_rounds == 0 {
+            answer.to_vec()
+        } else {
+            loss.refine(chunk, answer, options.refine_rounds)
+        }
+    };
+    // 検算して最良の解を更新する. 探索を止めるなら `true` を返す.
+    let mut check = |answer: &[InspectPoint]| {
+        let validation = loss.validate(chunk, answer);
+        report.add_candidate(loss.library(), answer, validation);
+        if best
+            .as_ref()
+            .is_none_or(|best| validation.score < best.validation.score)
+        {
+            best = Some(Best {
+                answer: answer.to_vec(),
+                validation,
+            });
+        }
+        if validation.is_valid() && options.deadline.is_none() {
+            return true;
+        }
+        if options.expired() {
+            warn!("search deadline passed");
+            return true;
+        }
+        if manual
+            .as_mut()
+            .is_some_and(|manual| manual.poll(loss.library()))
+        {
+            info!("manual constraints changed, searching again");
+            interrupted = true;
+            return true;
+        }
+        false
+    };
+
+    let solutions = constraints.solutions(data);
+    if options.branch_and_bound {
+        // 残差のより小さい組み合わせが見つかるたびに検算する
+        let found = BranchAndBound::new(
+            loss,
+            chunk,
+            points_by_loss,
+            constraints,
+            solutions,
+            BRANCH_AND_BOUND_CANDIDATES,
+        )
+        .search_until(options.deadline, |answer| check(&refine(answer)));
+        info!("branch and bound found: {:?}", found);
+        return Search { best, interrupted };
+    }
+
+    let (first_answer, rest) = constraints.first_answer(points_by_loss, solutions);
+    let first_answer = refine(&first_answer);
 
     info!("first answer is: {:?}", first_answer);
 
     // この最初に見つけた解が問題に一致するかどうか検算
-    if loss.validate(chunk, first_answer) {
-        requester.post_answer(&Answer {
-            problem_id: problem_info.id,
-            answers: first_answer
-                .iter()
-                .map(|p| p.using_voice.into_answer_string())
-                .collect(),
-        })?;
-        return Ok(());
+    if check(&first_answer) {
+        return Search { best, interrupted };
     }
 
     // 違うようなので, 最初の解から 1 つだけ取り除いて別の解を探す
-    for &next_candidate in &points_by_loss[solutions..] {
-        for to_remove in 0..first_answer.len() {
+    'search: for &next_candidate in &rest {
+        // 解に入っている札の別の遅延なら, その札だけを置き換える
+        let same_card = first_answer
+            .iter()
+            .position(|point| point.using_voice == next_candidate.using_voice);
+        let positions = match same_card {
+            Some(at) => at..at + 1,
+            None => 0..first_answer.len(),
+        };
+        for to_remove in positions {
+            if same_card.is_none() && !constraints.is_swappable(&first_answer[to_remove]) {
+                continue;
+            }
             let next_answer = {
                 let mut list = first_answer.to_vec();
                 list[to_remove] = next_candidate;
                 list
             };
-            if loss.validate(chunk, &next_answer) {
-                requester.post_answer(&Answer {
-                    problem_id: problem_info.id,
-                    answers: next_answer
-                        .into_iter()
-                        .map(|p| p.using_voice.into_answer_string())
-                        .collect(),
-                })?;
-                return Ok(());
+            if check(&next_answer) {
+                break 'search;
             }
         }
     }
 
-    todo!()
+    Search { best, interrupted }
 }
@@ -0,0 +1,123 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+
+use procon2022_comp_2nd::solve::{
+    card_voice::CardLibrary,
+    constraint::{Command, Constraints},
+};
+
+/// 操作者からの制約の入力. 別スレッドで 1 行ずつ読み, 解答中に受け取る.
+pub struct ManualInput {
+    lines: Receiver<String>,
+    constraints: Constraints,
+}
+
+impl ManualInput {
+    /// 環境変数 `MANUAL_INPUT` が `-` なら標準入力を, それ以外ならそのファイルを読む.
+    ///
+    /// ファイルは `tail -f` のように追記を待ち続ける. 環境変数が無ければ `None` を返す.
+    pub fn from_env() -> io::Result<Option<Self>> {
+        let Ok(source) = std::env::var("MANUAL_INPUT") else {
+            return Ok(None);
+        };
+        let (sender, lines) = mpsc::channel();
+        if source == "-" {
+            info!("reading manual constraints from stdin");
+            thread::spawn(move || {
+                for line in io::stdin().lines().map_while(Result::ok) {
+                    if sender.send(line).is_err() {
+                        break;
+                    }
+                }
+            });
+        } else {
+            info!("reading manual constraints from: {source}");
+            let mut reader = BufReader::new(File::open(&source)?);
+            thread::spawn(move || {
+                let mut line = String::new();
+                loop {
+                    line.clear();
+                    match reader.read_line(&mut line) {
+                        Ok(0) => thread::sleep(Duration::from_millis(200)),
+                        Ok(_) => {
+                            if sender.send(line.clone()).is_err() {
+                                break;
+                            }
+                        }
+                        Err(err) => {
+                            warn!("failed to read manual constraints: {err}");
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+        Ok(Some(Self {
+            lines,
+            constraints: Constraints::default(),
+        }))
+    }
+
+    #[inline]
+    pub fn constraints(&self) -> &Constraints {
+        &self.constraints
+    }
+
+    /// 届いている入力をすべて反映する. 制約が変わったら `true` を返す.
+    pub fn poll(&mut self, library: &CardLibrary) -> bool {
+        let mut changed = false;
+        loop {
+            match self.lines.try_recv() {
+                Ok(line) => changed |= self.apply(library, &line),
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => return changed,
+            }
+        }
+    }
+
+    /// 制約が変わるまで入力を待つ. 入力が閉じられるか `deadline` を過ぎたら `false` を返す.
+    pub fn wait(&mut self, library: &CardLibrary, deadline: Option<Instant>) -> bool {
+        loop {
+            let line = match deadline {
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    match self.lines.recv_timeout(timeout) {
+                        Ok(line) => line,
+                        Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => {
+                            return false
+                        }
+                    }
+                }
+                None => match self.lines.recv() {
+                    Ok(line) => line,
+                    Err(_) => return false,
+                },
+            };
+            if self.apply(library, &line) {
+                self.poll(library);
+                return true;
+            }
+        }
+    }
+
+    fn apply(&mut self, library: &CardLibrary, line: &str) -> bool {
+        match Command::parse(library, line) {
+            Ok(Some(command)) => {
+                self.constraints.apply(command);
+                info!("manual constraints: {:?}", self.constraints);
+                true
+            }
+            Ok(None) => false,
+            Err(err) => {
+                warn!("ignored manual input: {err}");
+                false
+            }
+        }
+    }
+}
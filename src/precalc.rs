@@ -1,23 +1,22 @@
-use std::{collections::HashMap, fs::File, io, path::PathBuf};
+use std::{collections::HashMap, fs};
 
 use log::info;
 
 use crate::{
     audio_vec::owned::{pixel::Pixel, Owned},
-    solve::card_voice::CardVoiceIndex,
+    decode::{read_pcm, DecodeError, SampleRatePolicy},
+    solve::card_voice::{CardLibrary, CardVoiceIndex},
 };
 
-pub fn load_all_jk() -> io::Result<HashMap<CardVoiceIndex, Owned>> {
+/// `library` に登録されたすべての読み札の音声を読み込む.
+pub fn load_card_voices(
+    library: &CardLibrary,
+) -> Result<HashMap<CardVoiceIndex, Owned>, DecodeError> {
     let mut map = HashMap::new();
-    for idx in CardVoiceIndex::all() {
-        let path: PathBuf = ["assets".into(), "jk".into(), format!("{}.wav", idx)]
-            .into_iter()
-            .collect();
-        let data = wav::read(&mut File::open(&path)?)?.1;
-        let pcm = data
-            .try_into_sixteen()
-            .expect("input audio bit-depth must be 16-bit");
-        map.insert(idx, Owned::from_pcm(&pcm));
+    for idx in library.all() {
+        let path = &library.card(idx).path;
+        let pcm = read_pcm(&fs::read(path)?)?;
+        map.insert(idx, pcm.into_owned(SampleRatePolicy::Check)?);
         info!("loaded speech voice: {}", path.display());
     }
     Ok(map)
@@ -35,23 +34,33 @@ impl Precalculation {
     pub fn new(card_voices: &HashMap<CardVoiceIndex, Owned>) -> Precalculation {
         let mut table = HashMap::new();
         for (&using, voice) in card_voices.iter() {
-            let squared = voice.squared();
-            let partial_sum: Vec<_> = squared
-                .scan(Pixel::default(), |acc, x| {
-                    *acc += x;
-                    Some(*acc)
-                })
-                .collect();
-            table.insert(using, partial_sum);
+            table.insert(using, squared_prefix_sum(voice));
             info!("pre-calculated: {:?}", using);
         }
         Self { table }
     }
 
-    pub fn get(&self, using: CardVoiceIndex, delay: isize) -> Pixel {
-        (0 <= delay)
-            .then(|| self.table[&using].get(delay as usize).copied())
-            .flatten()
-            .unwrap_or_default()
+    /// `f(index)` を返す. `index` が負なら 0, 音声の長さ以上なら全体の和になる.
+    pub fn get(&self, using: CardVoiceIndex, index: isize) -> Pixel {
+        prefix_sum_at(&self.table[&using], index)
     }
 }
+
+/// 音声を 2 乗したものの累積和を求める.
+pub fn squared_prefix_sum(voice: &Owned) -> Vec<Pixel> {
+    voice
+        .squared()
+        .scan(Pixel::default(), |acc, x| {
+            *acc += x;
+            Some(*acc)
+        })
+        .collect()
+}
+
+/// 累積和 `table` の `index` 番目を返す. `index` が負なら 0, 長さ以上なら全体の和になる.
+pub fn prefix_sum_at(table: &[Pixel], index: isize) -> Pixel {
+    if index < 0 || table.is_empty() {
+        return Pixel::default();
+    }
+    table[(index as usize).min(table.len() - 1)]
+}
@@ -0,0 +1,214 @@
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use log::info;
+use serde::Serialize;
+
+use procon2022_comp_2nd::{
+    request::{Answer, AnswerResponse, Problem},
+    solve::{card_voice::CardLibrary, InspectPoint, Validation},
+};
+
+/// 環境変数 `REPORT_DIR` が無いときに解答の記録を書き出す場所.
+pub const DEFAULT_REPORT_DIR: &str = "reports";
+
+#[derive(Debug, Serialize)]
+pub struct PointReport {
+    pub label: String,
+    pub answer: String,
+    pub delay: isize,
+    pub score: u64,
+}
+
+impl PointReport {
+    pub fn new(library: &CardLibrary, point: &InspectPoint) -> Self {
+        Self {
+            label: library.label(point.using_voice).to_owned(),
+            answer: library.answer(point.using_voice).to_owned(),
+            delay: point.delay,
+            score: point.score,
+        }
+    }
+}
+
+/// 検算した解の候補と, その残差.
+#[derive(Debug, Serialize)]
+pub struct CandidateReport {
+    pub points: Vec<PointReport>,
+    pub residual: u64,
+    pub valid: bool,
+}
+
+/// 各段階にかかった時間 (ミリ秒).
+#[derive(Debug, Default, Serialize)]
+pub struct Timings {
+    pub fetch_ms: u64,
+    pub find_points_ms: u64,
+    pub search_ms: u64,
+    pub post_ms: u64,
+    pub total_ms: u64,
+}
+
+impl Timings {
+    pub fn millis(duration: Duration) -> u64 {
+        duration.as_millis() as u64
+    }
+}
+
+/// 問題 1 つに対する解答の過程の記録.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub problem: Problem,
+    pub points: Vec<PointReport>,
+    /// 残差から推定した読み札の枚数
+    pub estimated_count: Option<usize>,
+    pub candidates: Vec<CandidateReport>,
+    pub posted: Option<Answer>,
+    pub response: Option<AnswerResponse>,
+    /// 期限が来て探索を打ち切ったか
+    pub timed_out: bool,
+    pub timings: Timings,
+}
+
+impl Report {
+    pub fn new(problem: Problem) -> Self {
+        Self {
+            problem,
+            points: vec![],
+            estimated_count: None,
+            candidates: vec![],
+            posted: None,
+            response: None,
+            timed_out: false,
+            timings: Timings::default(),
+        }
+    }
+
+    pub fn set_points(&mut self, library: &CardLibrary, points: &[InspectPoint]) {
+        self.points = points
+            .iter()
+            .map(|point| PointReport::new(library, point))
+            .collect();
+    }
+
+    pub fn add_candidate(
+        &mut self,
+        library: &CardLibrary,
+        answer: &[InspectPoint],
+        validation: Validation,
+    ) {
+        self.candidates.push(CandidateReport {
+            points: answer
+                .iter()
+                .map(|point| PointReport::new(library, point))
+                .collect(),
+            residual: validation.score,
+            valid: validation.is_valid(),
+        });
+    }
+
+    /// 環境変数 `REPORT_DIR` の, 無ければ [`DEFAULT_REPORT_DIR`] のディレクトリを返す.
+    pub fn dir_from_env() -> PathBuf {
+        std::env::var("REPORT_DIR")
+            .unwrap_or_else(|_| DEFAULT_REPORT_DIR.into())
+            .into()
+    }
+
+    /// `dir` に `<problem id>.json` として書き出す.
+    ///
+    /// 英数字と `-`, `_` 以外のバイトは `%2F` のようにパーセントエンコードするので, 異なる id が同じファイルになることはない.
+    pub fn write(&self, dir: &Path) -> anyhow::Result<PathBuf> {
+        fs::create_dir_all(dir)?;
+        let file_name: String = self
+            .problem
+            .id
+            .bytes()
+            .map(|b| match b {
+                b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' | b'-' | b'_' => (b as char).to_string(),
+                _ => format!("%{b:02X}"),
+            })
+            .collect();
+        let path = dir.join(format!("{file_name}.json"));
+        serde_json::to_writer_pretty(File::create(&path)?, self)?;
+        info!("wrote report: {}", path.display());
+        Ok(path)
+    }
+}
+
+#[test]
+fn report_serialization() {
+    use procon2022_comp_2nd::solve::card_voice::{Card, Language};
+
+    let library = CardLibrary::new(vec![Card {
+        path: "E01.wav".into(),
+        label: "E01".into(),
+        answer: "01".into(),
+        language: Language::En,
+    }])
+    .unwrap();
+    let point = InspectPoint {
+        using_voice: library.find("E01").unwrap(),
+        delay: -4800,
+        score: 123,
+    };
+    let mut report = Report::new(Problem {
+        id: "qual-1/2".into(),
+        chunks: 3,
+        start_at: 1667005344340,
+        time_limit: 1000,
+        data: 1,
+    });
+    report.set_points(&library, &[point]);
+    report.add_candidate(&library, &[point], Validation { score: 5 });
+    report.estimated_count = Some(1);
+
+    let dir = std::env::temp_dir().join(format!("report_serialization_{}", std::process::id()));
+    let path = report.write(&dir).unwrap();
+    assert_eq!(path.file_name().unwrap(), "qual-1%2F2.json");
+    let json: serde_json::Value = serde_json::from_reader(File::open(&path).unwrap()).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(json["problem"]["id"], "qual-1/2");
+    assert_eq!(json["points"][0]["label"], "E01");
+    assert_eq!(json["points"][0]["answer"], "01");
+    assert_eq!(json["points"][0]["delay"], -4800);
+    assert_eq!(json["estimated_count"], 1);
+    assert_eq!(json["candidates"][0]["residual"], 5);
+    assert_eq!(json["candidates"][0]["valid"], true);
+    assert_eq!(json["posted"], serde_json::Value::Null);
+    assert_eq!(json["timed_out"], false);
+    assert_eq!(json["timings"]["total_ms"], 0);
+}
+
+#[test]
+fn report_file_names_do_not_collide() {
+    let dir = std::env::temp_dir().join(format!("report_file_names_{}", std::process::id()));
+    let paths: Vec<_> = ["qual-1", "qual_1", "qual/1", "qual%2F1"]
+        .into_iter()
+        .map(|id| {
+            let report = Report::new(Problem {
+                id: id.into(),
+                chunks: 1,
+                start_at: 0,
+                time_limit: 0,
+                data: 1,
+            });
+            report.write(&dir).unwrap()
+        })
+        .collect();
+    fs::remove_dir_all(&dir).unwrap();
+
+    let names: Vec<_> = paths.iter().map(|path| path.file_name().unwrap()).collect();
+    assert_eq!(
+        names,
+        [
+            "qual-1.json",
+            "qual_1.json",
+            "qual%2F1.json",
+            "qual%252F1.json"
+        ]
+    );
+}
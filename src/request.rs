@@ -1,14 +1,18 @@
-use anyhow::Result;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::audio_vec::owned::Owned;
+use crate::audio_vec::{composite::Stitched, owned::Owned, AudioVec};
 
 pub mod mock;
 pub mod net;
+pub mod record;
+pub mod replay;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Match {
     pub problems: u32,
     pub bonus_factor: Vec<f64>,
@@ -18,7 +22,7 @@ pub struct Match {
     pub correct_point: u32,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Problem {
     pub id: String,
     pub chunks: u32,
@@ -27,18 +31,27 @@ pub struct Problem {
     pub data: u32,
 }
 
+impl Problem {
+    /// 解答の締め切り. `start_at` は UNIX 時間 (ミリ秒), `time_limit` は秒で与えられる.
+    ///
+    /// `start_at` の単位はモックの値 `1667005344340` (2022-10-29) に合わせている.
+    pub fn deadline(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.start_at) + Duration::from_secs(self.time_limit)
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Chunks {
     pub chunks: Vec<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Answer {
     pub problem_id: String,
     pub answers: Vec<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AnswerResponse {
     pub problem_id: String,
     pub answers: Vec<String>,
@@ -68,5 +81,71 @@ pub trait Requester {
 
     fn get_chunks(&self, using_chunks: u8) -> Result<Vec<Owned>>;
 
+    /// 先頭から取得した分割データ `chunks` それぞれの, 問題全体の中での開始位置.
+    ///
+    /// 既定では前の分割データの長さを順に足したもの. 分割データの長さを別に知っていれば, それで求める.
+    fn chunk_offsets(&self, chunks: &[Owned]) -> Vec<usize> {
+        chunks
+            .iter()
+            .scan(0, |start, chunk| {
+                let offset = *start;
+                *start += chunk.len();
+                Some(offset)
+            })
+            .collect()
+    }
+
+    /// 先頭から `using_chunks` 個の分割データを取得し, 順につなげて 1 つの音声にする.
+    fn get_problem_voice(&self, using_chunks: u8) -> Result<Owned> {
+        let stitched = Stitched::contiguous(self.get_chunks(using_chunks)?);
+        Ok(stitched.to_owned(stitched.len()))
+    }
+
+    /// 1 から数えた番号が `selection` に含まれる分割データだけを, 問題全体の中での位置に置いて 1 つの音声にする.
+    /// 長さは取得した最後の分割データの終わりまでで, 選ばなかった分割データの範囲は 0 になる.
+    ///
+    /// 0 にした範囲も問題の一部として損失を求める. 2 乗誤差では, そこに重なる読み札の 2 乗ノルムがそのまま損失に加わるので,
+    /// 選ばなかった範囲に読み札が重なる遅延ほど不利になる.
+    fn get_selected_problem_voice(&self, selection: &[u8]) -> Result<Owned> {
+        if selection.contains(&0) {
+            return Err(anyhow!("chunks are numbered from 1: {selection:?}"));
+        }
+        let using_chunks = *selection
+            .iter()
+            .max()
+            .ok_or_else(|| anyhow!("no chunk selected"))?;
+        let chunks = self.get_chunks(using_chunks)?;
+        let offsets = self.chunk_offsets(&chunks);
+        let len = offsets
+            .iter()
+            .zip(&chunks)
+            .map(|(offset, chunk)| offset + chunk.len())
+            .max()
+            .unwrap_or(0);
+        let (offsets, chunks): (Vec<_>, Vec<_>) = offsets
+            .into_iter()
+            .zip(chunks)
+            .zip(1..)
+            .filter(|(_, number)| selection.contains(number))
+            .map(|(part, _)| part)
+            .unzip();
+        Ok(Stitched::new(offsets, chunks).to_owned(len))
+    }
+
     fn post_answer(&self, answer: &Answer) -> Result<AnswerResponse>;
 }
+
+#[test]
+fn problem_deadline() {
+    let problem = Problem {
+        id: "qual-1-1".into(),
+        chunks: 3,
+        start_at: 1667005344340,
+        time_limit: 300,
+        data: 3,
+    };
+    assert_eq!(
+        problem.deadline().duration_since(UNIX_EPOCH).unwrap(),
+        Duration::from_millis(1667005344340 + 300 * 1000)
+    );
+}
@@ -2,7 +2,7 @@ use std::{fs::File, path::PathBuf};
 
 use serde::Deserialize;
 
-use crate::audio_vec::owned::Owned;
+use crate::{audio_vec::owned::Owned, decode::decode_wav};
 
 use super::Requester;
 
@@ -46,6 +46,11 @@ impl MockRequester {
             durations: info.durations(),
         }
     }
+
+    /// 問題に含まれる読み札の名前.
+    pub fn speeches(&self) -> &[String] {
+        &self.speeches
+    }
 }
 
 impl Requester for MockRequester {
@@ -70,12 +75,26 @@ impl Requester for MockRequester {
         })
     }
 
-    fn get_chunks(&self, _using_chunks: u8) -> anyhow::Result<Vec<Owned>> {
-        let data = wav::read(&mut File::open(self.using_path.join("problem1.wav"))?)?.1;
-        let pcm = data
-            .try_into_sixteen()
-            .expect("input audio bit-depth must be 16-bit");
-        Ok(vec![Owned::from_pcm(&pcm)])
+    fn get_chunks(&self, using_chunks: u8) -> anyhow::Result<Vec<Owned>> {
+        (1..=using_chunks.max(1))
+            .map(|i| {
+                let bytes = std::fs::read(self.using_path.join(format!("problem{i}.wav")))?;
+                Ok(decode_wav(&bytes)?)
+            })
+            .collect()
+    }
+
+    /// `information.txt` の分割長から求める.
+    fn chunk_offsets(&self, chunks: &[Owned]) -> Vec<usize> {
+        self.durations
+            .iter()
+            .take(chunks.len())
+            .scan(0, |start, &duration| {
+                let offset = *start;
+                *start += duration as usize;
+                Some(offset)
+            })
+            .collect()
     }
 
     fn post_answer(&self, answer: &super::Answer) -> anyhow::Result<super::AnswerResponse> {
@@ -87,3 +106,28 @@ impl Requester for MockRequester {
         })
     }
 }
+
+#[test]
+fn stitch_sample_chunks() -> anyhow::Result<()> {
+    use crate::audio_vec::{composite::Stitched, AudioVec};
+
+    let requester = MockRequester::new(["assets", "sample", "sample_Q_E01"].into_iter().collect());
+    let whole = decode_wav(&std::fs::read(requester.using_path.join("problem.wav"))?)?;
+    let mut chunks = requester.get_chunks(2)?;
+    let offsets = requester.chunk_offsets(&chunks);
+
+    let stitched = Stitched::new(offsets.clone(), chunks.clone());
+    assert_eq!(stitched.len(), whole.len());
+    assert_eq!(stitched.to_owned(whole.len()), whole);
+
+    // 2 つ目の分割データだけなら, 1 つ目の範囲は 0 になる
+    let second = Stitched::new([offsets[1]], vec![chunks.remove(1)]);
+    let expected = Owned::default()
+        .concat(offsets[1], (&whole).delay(offsets[1] as isize))
+        .to_owned(whole.len());
+    assert_eq!(second.to_owned(whole.len()), expected);
+    assert_eq!(requester.get_selected_problem_voice(&[2])?, expected);
+    assert_eq!(requester.get_selected_problem_voice(&[2, 1])?, whole);
+    assert!(requester.get_selected_problem_voice(&[0, 1]).is_err());
+    Ok(())
+}
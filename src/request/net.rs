@@ -1,12 +1,10 @@
-use std::io::Cursor;
-
 use reqwest::{
     blocking::Client,
     header::{HeaderMap, HeaderValue},
     Url,
 };
 
-use crate::audio_vec::owned::Owned;
+use crate::{audio_vec::owned::Owned, decode::decode_wav};
 
 use super::{Error, Requester};
 
@@ -30,31 +28,52 @@ impl NetRequester {
                 .expect("invalid header map"),
         }
     }
-}
 
-impl Requester for NetRequester {
-    fn get_match(&self) -> anyhow::Result<super::Match> {
+    /// 音声の分割データを, WAV ファイルの名前と中身のバイト列のまま取得する.
+    pub fn get_raw_chunks(&self, using_chunks: u8) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        let chunks_url = self.endpoint.join("/problem/chunks").unwrap();
         let res = self
             .client
-            .get(self.endpoint.join("/match").unwrap())
+            .post(chunks_url.clone())
+            .query(&[("n", using_chunks)])
             .send()?;
         let status = res.status();
         if status.is_client_error() || status.is_server_error() {
             return Err(match res.text()?.as_str().trim() {
                 "InvalidToken" => Error::InvalidToken,
                 "AccessTimeError" => Error::AccessTime,
+                "FormatError" => Error::Format,
                 _ => Error::Unknown(status),
             }
             .into());
         }
-        let json: super::Match = res.json()?;
-        Ok(json)
+        let json: super::Chunks = res.json()?;
+        json.chunks
+            .into_iter()
+            .map(|chunk| {
+                let res = self.client.get(chunks_url.join(&chunk).unwrap()).send()?;
+                let status = res.status();
+                if status.is_client_error() || status.is_server_error() {
+                    return Err(match res.text()?.as_str().trim() {
+                        "InvalidToken" => Error::InvalidToken,
+                        "AccessTimeError" => Error::AccessTime,
+                        "NotFound" => Error::NotFound(chunk),
+                        _ => Error::Unknown(status),
+                    }
+                    .into());
+                }
+                let bytes = res.bytes()?;
+                Ok((chunk, bytes.to_vec()))
+            })
+            .collect()
     }
+}
 
-    fn get_problem(&self) -> anyhow::Result<super::Problem> {
+impl Requester for NetRequester {
+    fn get_match(&self) -> anyhow::Result<super::Match> {
         let res = self
             .client
-            .get(self.endpoint.join("/problem").unwrap())
+            .get(self.endpoint.join("/match").unwrap())
             .send()?;
         let status = res.status();
         if status.is_client_error() || status.is_server_error() {
@@ -65,50 +84,32 @@ impl Requester for NetRequester {
             }
             .into());
         }
-        let json: super::Problem = res.json()?;
+        let json: super::Match = res.json()?;
         Ok(json)
     }
 
-    fn get_chunks(&self, using_chunks: u8) -> anyhow::Result<Vec<Owned>> {
-        let chunks_url = self.endpoint.join("/problem/chunks").unwrap();
+    fn get_problem(&self) -> anyhow::Result<super::Problem> {
         let res = self
             .client
-            .post(chunks_url.clone())
-            .query(&[("n", using_chunks)])
+            .get(self.endpoint.join("/problem").unwrap())
             .send()?;
         let status = res.status();
         if status.is_client_error() || status.is_server_error() {
             return Err(match res.text()?.as_str().trim() {
                 "InvalidToken" => Error::InvalidToken,
                 "AccessTimeError" => Error::AccessTime,
-                "FormatError" => Error::Format,
                 _ => Error::Unknown(status),
             }
             .into());
         }
-        let json: super::Chunks = res.json()?;
-        json.chunks
+        let json: super::Problem = res.json()?;
+        Ok(json)
+    }
+
+    fn get_chunks(&self, using_chunks: u8) -> anyhow::Result<Vec<Owned>> {
+        self.get_raw_chunks(using_chunks)?
             .into_iter()
-            .map(|chunk| {
-                let res = self.client.get(chunks_url.join(&chunk).unwrap()).send()?;
-                let status = res.status();
-                if status.is_client_error() || status.is_server_error() {
-                    return Err(match res.text()?.as_str().trim() {
-                        "InvalidToken" => Error::InvalidToken,
-                        "AccessTimeError" => Error::AccessTime,
-                        "NotFound" => Error::NotFound(chunk),
-                        _ => Error::Unknown(status),
-                    }
-                    .into());
-                }
-                let bytes = res.bytes()?;
-                let mut cursor = Cursor::new(bytes);
-                let pcm = wav::read(&mut cursor)?
-                    .1
-                    .try_into_sixteen()
-                    .expect("expected 16-bit depth wav file");
-                Ok(Owned::from_pcm(&pcm))
-            })
+            .map(|(_, bytes)| Ok(decode_wav(&bytes)?))
             .collect()
     }
 
@@ -0,0 +1,194 @@
+use std::{
+    cell::Cell,
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{audio_vec::owned::Owned, decode::decode_wav};
+
+use super::{net::NetRequester, Answer, AnswerResponse, Match, Problem, Requester};
+
+/// サーバとの 1 回のやり取り. 記録ディレクトリに `0000.json`, `0001.json`, ... の連番で保存される.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub(super) enum Exchange {
+    GetMatch {
+        response: Recorded<Match>,
+    },
+    GetProblem {
+        response: Recorded<Problem>,
+    },
+    GetChunks {
+        using_chunks: u8,
+        /// 受け取った WAV ファイルを保存したファイル名の一覧.
+        response: Recorded<Vec<String>>,
+    },
+    PostAnswer {
+        answer: Answer,
+        response: Recorded<AnswerResponse>,
+    },
+}
+
+impl Exchange {
+    pub(super) fn method(&self) -> &'static str {
+        match self {
+            Exchange::GetMatch { .. } => "get_match",
+            Exchange::GetProblem { .. } => "get_problem",
+            Exchange::GetChunks { .. } => "get_chunks",
+            Exchange::PostAnswer { .. } => "post_answer",
+        }
+    }
+}
+
+/// リクエストの結果. エラーはそのメッセージだけを残す.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum Recorded<T> {
+    Ok(T),
+    Err(String),
+}
+
+impl<T: Clone> Recorded<T> {
+    fn record(result: &anyhow::Result<T>) -> Self {
+        match result {
+            Ok(value) => Recorded::Ok(value.clone()),
+            Err(err) => Recorded::Err(err.to_string()),
+        }
+    }
+}
+
+impl<T> Recorded<T> {
+    pub(super) fn into_result(self) -> anyhow::Result<T> {
+        match self {
+            Recorded::Ok(value) => Ok(value),
+            Recorded::Err(message) => Err(anyhow::anyhow!(message)),
+        }
+    }
+}
+
+pub(super) fn exchange_path(dir: &Path, sequence: usize) -> PathBuf {
+    dir.join(format!("{sequence:04}.json"))
+}
+
+fn chunk_file_name(sequence: usize, index: usize) -> String {
+    format!("{sequence:04}-{index}.wav")
+}
+
+/// [`NetRequester`] でのやり取りを, 受け取った WAV ファイルのバイト列も含めてすべてディレクトリに記録する.
+///
+/// 記録したディレクトリは [`ReplayRequester`](super::replay::ReplayRequester) でオフラインに再生できる.
+pub struct RecordRequester {
+    inner: NetRequester,
+    dir: PathBuf,
+    sequence: Cell<usize>,
+}
+
+impl RecordRequester {
+    pub fn new(inner: NetRequester, dir: PathBuf) -> anyhow::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        info!("recording session into: {}", dir.display());
+        Ok(Self {
+            inner,
+            dir,
+            sequence: Cell::new(0),
+        })
+    }
+
+    fn next_sequence(&self) -> usize {
+        let sequence = self.sequence.get();
+        self.sequence.set(sequence + 1);
+        sequence
+    }
+
+    fn record_chunks(
+        &self,
+        sequence: usize,
+        using_chunks: u8,
+        result: &anyhow::Result<Vec<(String, Vec<u8>)>>,
+    ) -> anyhow::Result<()> {
+        let response = match result {
+            Ok(chunks) => {
+                let mut names = vec![];
+                for (index, (chunk, bytes)) in chunks.iter().enumerate() {
+                    let name = chunk_file_name(sequence, index);
+                    fs::write(self.dir.join(&name), bytes)?;
+                    info!("recorded chunk {chunk} as {name}");
+                    names.push(name);
+                }
+                Recorded::Ok(names)
+            }
+            Err(err) => Recorded::Err(err.to_string()),
+        };
+        self.write(
+            sequence,
+            &Exchange::GetChunks {
+                using_chunks,
+                response,
+            },
+        )
+    }
+
+    fn write(&self, sequence: usize, exchange: &Exchange) -> anyhow::Result<()> {
+        let file = File::create(exchange_path(&self.dir, sequence))?;
+        serde_json::to_writer_pretty(file, exchange)?;
+        Ok(())
+    }
+}
+
+impl Requester for RecordRequester {
+    fn get_match(&self) -> anyhow::Result<Match> {
+        let sequence = self.next_sequence();
+        let result = self.inner.get_match();
+        self.write(
+            sequence,
+            &Exchange::GetMatch {
+                response: Recorded::record(&result),
+            },
+        )?;
+        result
+    }
+
+    fn get_problem(&self) -> anyhow::Result<Problem> {
+        let sequence = self.next_sequence();
+        let result = self.inner.get_problem();
+        self.write(
+            sequence,
+            &Exchange::GetProblem {
+                response: Recorded::record(&result),
+            },
+        )?;
+        result
+    }
+
+    fn get_chunks(&self, using_chunks: u8) -> anyhow::Result<Vec<Owned>> {
+        let sequence = self.next_sequence();
+        let result = self.inner.get_raw_chunks(using_chunks);
+        // 分割データはもう受け取ったので, 記録に失敗しても解くのは続ける
+        if let Err(err) = self.record_chunks(sequence, using_chunks, &result) {
+            warn!("failed to record the chunks: {err}");
+        }
+        result?
+            .into_iter()
+            .map(|(_, bytes)| Ok(decode_wav(&bytes)?))
+            .collect()
+    }
+
+    fn post_answer(&self, answer: &Answer) -> anyhow::Result<AnswerResponse> {
+        let sequence = self.next_sequence();
+        let result = self.inner.post_answer(answer);
+        // 解答はもう送ったので, 記録に失敗してもサーバの結果を返す
+        if let Err(err) = self.write(
+            sequence,
+            &Exchange::PostAnswer {
+                answer: answer.clone(),
+                response: Recorded::record(&result),
+            },
+        ) {
+            warn!("failed to record the posted answer: {err}");
+        }
+        result
+    }
+}
@@ -0,0 +1,129 @@
+use std::{cell::Cell, fs::File, path::PathBuf};
+
+use anyhow::{bail, Context};
+use log::{info, warn};
+
+use crate::{audio_vec::owned::Owned, decode::decode_wav};
+
+use super::{
+    record::{exchange_path, Exchange},
+    Answer, AnswerResponse, Match, Problem, Requester,
+};
+
+/// [`RecordRequester`](super::record::RecordRequester) で記録したやり取りを, 記録した順にそのまま返す.
+#[derive(Debug)]
+pub struct ReplayRequester {
+    dir: PathBuf,
+    sequence: Cell<usize>,
+}
+
+impl ReplayRequester {
+    pub fn new(dir: PathBuf) -> Self {
+        info!("replaying session from: {}", dir.display());
+        Self {
+            dir,
+            sequence: Cell::new(0),
+        }
+    }
+
+    fn next_exchange(&self, method: &str) -> anyhow::Result<Exchange> {
+        let sequence = self.sequence.get();
+        self.sequence.set(sequence + 1);
+        let path = exchange_path(&self.dir, sequence);
+        let file = File::open(&path)
+            .with_context(|| format!("no more recorded exchange: {}", path.display()))?;
+        let exchange: Exchange = serde_json::from_reader(file)?;
+        if exchange.method() != method {
+            bail!(
+                "replay diverged at {}: expected {method} but recorded {}",
+                path.display(),
+                exchange.method()
+            );
+        }
+        Ok(exchange)
+    }
+}
+
+impl Requester for ReplayRequester {
+    fn get_match(&self) -> anyhow::Result<Match> {
+        match self.next_exchange("get_match")? {
+            Exchange::GetMatch { response } => response.into_result(),
+            _ => unreachable!(),
+        }
+    }
+
+    fn get_problem(&self) -> anyhow::Result<Problem> {
+        match self.next_exchange("get_problem")? {
+            Exchange::GetProblem { response } => response.into_result(),
+            _ => unreachable!(),
+        }
+    }
+
+    fn get_chunks(&self, using_chunks: u8) -> anyhow::Result<Vec<Owned>> {
+        let Exchange::GetChunks {
+            using_chunks: recorded_chunks,
+            response,
+        } = self.next_exchange("get_chunks")?
+        else {
+            unreachable!()
+        };
+        if recorded_chunks != using_chunks {
+            bail!(
+                "replay diverged: requested {using_chunks} chunks but recorded {recorded_chunks}"
+            );
+        }
+        response
+            .into_result()?
+            .into_iter()
+            .map(|name| Ok(decode_wav(&std::fs::read(self.dir.join(name))?)?))
+            .collect()
+    }
+
+    fn post_answer(&self, answer: &Answer) -> anyhow::Result<AnswerResponse> {
+        let Exchange::PostAnswer {
+            answer: recorded_answer,
+            response,
+        } = self.next_exchange("post_answer")?
+        else {
+            unreachable!()
+        };
+        if &recorded_answer != answer {
+            warn!("posted {answer:?} but recorded {recorded_answer:?}");
+        }
+        response.into_result()
+    }
+}
+
+#[test]
+fn replay_recorded_session() -> anyhow::Result<()> {
+    use super::record::Recorded;
+
+    let dir = std::env::temp_dir().join(format!("replay_recorded_session_{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let sample: PathBuf = ["assets", "sample", "sample_Q_E01", "problem1.wav"]
+        .into_iter()
+        .collect();
+    std::fs::copy(&sample, dir.join("0001-0.wav"))?;
+    let exchanges = [
+        Exchange::GetProblem {
+            response: Recorded::Err("the match not started".into()),
+        },
+        Exchange::GetChunks {
+            using_chunks: 1,
+            response: Recorded::Ok(vec!["0001-0.wav".into()]),
+        },
+    ];
+    for (sequence, exchange) in exchanges.iter().enumerate() {
+        serde_json::to_writer(File::create(exchange_path(&dir, sequence))?, exchange)?;
+    }
+
+    let requester = ReplayRequester::new(dir.clone());
+    let err = requester.get_problem().unwrap_err();
+    assert_eq!(err.to_string(), "the match not started");
+    let chunks = requester.get_chunks(1)?;
+    assert_eq!(chunks, vec![decode_wav(&std::fs::read(&sample)?)?]);
+    assert!(requester.get_match().is_err());
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
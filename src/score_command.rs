@@ -0,0 +1,57 @@
+use std::{fs, path::PathBuf};
+
+use procon2022_comp_2nd::{
+    request::{mock::MockRequester, Requester},
+    solve::{scoring::Scoring, Loss},
+};
+
+/// `scoring [sample dir...]` を実行する. 各サンプルの最初の分割データについて, 損失の測り方ごとに正解の読み札が何位になるかを表示する.
+///
+/// サンプルを指定しなければ `assets/sample` 以下のすべてを使う.
+pub fn run(mut loss: Loss, args: &[String]) -> anyhow::Result<()> {
+    let samples: Vec<PathBuf> = if args.is_empty() {
+        let mut dirs: Vec<_> = fs::read_dir(["assets", "sample"].iter().collect::<PathBuf>())?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<_, _>>()?;
+        dirs.retain(|dir| dir.is_dir());
+        dirs.sort();
+        dirs
+    } else {
+        args.iter().map(PathBuf::from).collect()
+    };
+
+    println!("sample\tscoring\tranks\ttop-n correct");
+    for sample in samples {
+        let requester = MockRequester::new(sample.clone());
+        let chunk = &requester.get_chunks(1)?[0];
+        let speeches = requester.speeches();
+        for scoring in Scoring::ALL {
+            loss = loss.with_scoring(scoring);
+            let points = loss.find_points(chunk);
+            let ranks: Vec<_> = speeches
+                .iter()
+                .map(|speech| {
+                    points
+                        .iter()
+                        .position(|point| loss.library().label(point.using_voice) == speech)
+                        .map_or("-".to_owned(), |rank| (rank + 1).to_string())
+                })
+                .collect();
+            let correct = points[..speeches.len()]
+                .iter()
+                .filter(|point| {
+                    speeches
+                        .iter()
+                        .any(|s| s == loss.library().label(point.using_voice))
+                })
+                .count();
+            println!(
+                "{}\t{scoring}\t{}\t{correct}/{}",
+                sample.display(),
+                ranks.join(","),
+                speeches.len()
+            );
+        }
+    }
+    Ok(())
+}
@@ -1,18 +1,34 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, ops::Range, sync::OnceLock, time::Instant};
 
 use log::info;
 
 use crate::{
     audio_vec::{
-        owned::{ntt::Ntt, Owned},
+        owned::{
+            fft::Fft,
+            moduli::Moduli,
+            overlap_save::OverlapSaveKernel,
+            pixel::{Pixel, PixelModuli},
+            ExactNtts, Owned,
+        },
         AudioVec,
     },
-    precalc::Precalculation,
+    precalc::{prefix_sum_at, squared_prefix_sum, Precalculation},
 };
 
-use self::card_voice::CardVoiceIndex;
+use self::{
+    card_voice::{CardLibrary, CardVoiceIndex},
+    mixing::Mixing,
+    scoring::Scoring,
+    spectrogram::Spectrogram,
+};
 
+pub mod branch_bound;
 pub mod card_voice;
+pub mod constraint;
+pub mod mixing;
+pub mod scoring;
+pub mod spectrogram;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct InspectPoint {
@@ -24,141 +40,871 @@ pub struct InspectPoint {
 /// 損失関数のオブジェクト
 #[derive(Debug)]
 pub struct Loss {
-    /// 88 個の読み札の読み上げ音声
+    /// 読み札の一覧
+    library: CardLibrary,
+    /// 読み札の読み上げ音声
     card_voices: HashMap<CardVoiceIndex, Owned>,
     flipped_card_voices: HashMap<CardVoiceIndex, Owned>,
+    /// 反転した読み札を overlap-save 法のために変換したもの. 長い問題で初めて使うときに変換する.
+    overlap_save_kernels: HashMap<CardVoiceIndex, OnceLock<OverlapSaveKernel>>,
     precalc: Precalculation,
     /// 数論変換のための前計算オブジェクト
-    ntt: (Ntt<924844033>, Ntt<998244353>),
+    ntt: <PixelModuli as Moduli>::Ntts,
+    /// 値が大きく既定の法で畳み込めないときに使う, 法を増やした数論変換の前計算
+    exact_ntts: ExactNtts,
+    /// 遅延ごとの良さの測り方
+    scoring: Scoring,
+    /// 検算で読み札を重ね合わせるときの, 範囲を超えた値の扱い
+    mixing: Mixing,
+    /// [`Self::find_points_top`] で損失に組み合わせるスペクトログラムの照合
+    spectrogram: Option<SpectrogramFusion>,
+    /// [`Self::find_points_top`] で先に候補を絞り込むための, 間引いた読み札の損失関数
+    coarse: Option<Coarse>,
+}
+
+/// 間引いた音声で候補を絞り込むための損失関数.
+#[derive(Debug)]
+struct Coarse {
+    loss: Box<Loss>,
+    factor: usize,
+}
+
+impl Coarse {
+    /// 間引いた音声で損失の小さい [`Loss::COARSE_SHORTLIST`] 個の候補を選び,
+    /// それぞれの遅延の前後 `factor` の範囲を `fine` で評価し直す.
+    fn find_points(
+        &self,
+        fine: &Loss,
+        problem_voice: &Owned,
+        k: usize,
+        deadline: Option<Instant>,
+    ) -> Vec<InspectPoint> {
+        let factor = self.factor as isize;
+        let decimated = problem_voice
+            .decimate(self.factor)
+            .to_owned(problem_voice.len().div_ceil(self.factor));
+        let stats = ProblemStats::new(problem_voice);
+        let mut points: Vec<_> = self
+            .loss
+            .find_points_until(&decimated, k, deadline)
+            .into_iter()
+            .take(Loss::COARSE_SHORTLIST)
+            .map(|point| {
+                let center = point.delay * factor;
+                fine.evaluate_window_with(
+                    problem_voice,
+                    &stats,
+                    point.using_voice,
+                    center - factor..center + factor + 1,
+                )
+            })
+            .collect();
+        // 近い遅延の候補が同じ遅延に落ち着くことがある
+        points.sort_unstable_by_key(|point| (point.using_voice, point.delay));
+        points.dedup();
+        points
+    }
+}
+
+/// 損失とスペクトログラムの照合を組み合わせて候補を並べるための前計算.
+#[derive(Debug)]
+struct SpectrogramFusion {
+    fft: Fft,
+    cards: HashMap<CardVoiceIndex, Spectrogram>,
+    /// 損失に `1 + weight × 照合の食い違い` を掛けて並べる
+    weight: f64,
+}
+
+impl SpectrogramFusion {
+    fn fuse(&self, problem: &Spectrogram, point: &InspectPoint) -> f64 {
+        let mismatch = problem.match_score(&self.cards[&point.using_voice], point.delay);
+        point.score as f64 * (1.0 + self.weight * mismatch)
+    }
 }
 
 impl Loss {
-    pub fn new(card_voices: HashMap<CardVoiceIndex, Owned>) -> Self {
+    /// [`Self::evaluate_top`] で選ぶ遅延どうしの最小の間隔. 10 ミリ秒分.
+    pub const MIN_DELAY_SEPARATION: usize = 480;
+    /// [`Self::with_coarse`] のとき, 間引いた音声で絞り込む候補の数.
+    pub const COARSE_SHORTLIST: usize = 48;
+    /// [`Self::estimate_count`] で札を加えるのに必要な, 残差の減る割合.
+    pub const MIN_RESIDUAL_REDUCTION: f64 = 0.1;
+    /// [`Self::estimate_count`] で, 残らない札が何枚続いたら打ち切るか.
+    pub const MAX_ESTIMATE_MISSES: usize = 3;
+    /// 問題が読み札のこれ倍より長ければ, 読み札のこれ倍程度の長さの数論変換で区切って畳み込む.
+    ///
+    /// 損失には問題全体の 2 乗ノルムが要るので, 分割データを受け取るたびに畳み込むことはせず, すべてつなげてから区切る.
+    pub const OVERLAP_SAVE_RATIO: usize = 4;
+
+    pub fn new(library: CardLibrary, card_voices: HashMap<CardVoiceIndex, Owned>) -> Self {
         let precalc = Precalculation::new(&card_voices);
         let flipped_card_voices = card_voices
             .iter()
-            .map(|(&idx, vec)| (idx, vec.clone().flip().to_owned(vec.len())))
+            .map(|(&idx, vec)| {
+                let len = vec.len();
+                (idx, vec.flip().delay(1 - len as isize).to_owned(len))
+            })
+            .collect();
+        let overlap_save_kernels = card_voices
+            .keys()
+            .map(|&idx| (idx, OnceLock::new()))
             .collect();
         Self {
+            library,
             card_voices,
             flipped_card_voices,
+            overlap_save_kernels,
             precalc,
-            ntt: (Ntt::new(), Ntt::new()),
+            ntt: Default::default(),
+            exact_ntts: Default::default(),
+            scoring: Scoring::default(),
+            mixing: Mixing::default(),
+            spectrogram: None,
+            coarse: None,
+        }
+    }
+
+    pub fn with_scoring(self, scoring: Scoring) -> Self {
+        Self { scoring, ..self }
+    }
+
+    pub fn with_mixing(self, mixing: Mixing) -> Self {
+        Self { mixing, ..self }
+    }
+
+    /// [`Self::find_points_top`] で, 先に `factor` 分の 1 に間引いた音声で候補を絞り込む. `factor` は 1 以上であること.
+    pub fn with_coarse(self, factor: usize) -> Self {
+        assert!(0 < factor, "coarse factor must be positive");
+        let card_voices = self
+            .card_voices
+            .iter()
+            .map(|(&index, voice)| {
+                let len = voice.len().div_ceil(factor);
+                (index, voice.decimate(factor).to_owned(len))
+            })
+            .collect();
+        let coarse = Loss::new(self.library.clone(), card_voices).with_scoring(self.scoring);
+        Self {
+            coarse: Some(Coarse {
+                loss: Box::new(coarse),
+                factor,
+            }),
+            ..self
         }
     }
 
+    /// 候補を並べるときに, 損失の近いものをスペクトログラムの照合で並べ替える.
+    pub fn with_spectrogram(self, weight: f64) -> Self {
+        let fft = Fft::new(Spectrogram::FRAME_LEVEL);
+        let cards = self
+            .card_voices
+            .iter()
+            .map(|(&index, voice)| (index, Spectrogram::new(voice, &fft)))
+            .collect();
+        Self {
+            spectrogram: Some(SpectrogramFusion { fft, cards, weight }),
+            ..self
+        }
+    }
+
+    #[inline]
+    pub fn library(&self) -> &CardLibrary {
+        &self.library
+    }
+
     /// 2 乗ノルムを用いた損失関数
     ///
     /// `problem_voice` は `card_voices` のうちからいくつかが選ばれて, 時間をずらして重ね合わせたもの
     #[inline]
     pub fn evaluate(&self, problem_voice: &Owned, using_voice: CardVoiceIndex) -> InspectPoint {
-        let convolution = problem_voice.convolution(
-            &self.flipped_card_voices[&using_voice],
-            (&self.ntt.0, &self.ntt.1),
-        );
-        let stride = (self.card_voices[&using_voice].len() - problem_voice.len()) as isize;
-        let squared_norm = problem_voice.squared_norm().as_u64();
-
-        let mut min_score = u64::MAX;
-        let mut min_delay = 0;
-        for delay in -(problem_voice.len() as isize)..stride {
-            // R : using voice
-            // T : problem voice length
-            // x : problem voice
-            // w : how long delayed
-            // f(w) = |x - R.delayed(w).clip()|^2
-            // = |x|^2 - 2 * x * R.delayed(w).clip() + |R.delayed(w)|^2
-            // = |x|^2 - 2 * x * R.delayed(w) + |R.delayed(w)|^2
-            // = |x|^2 - 2 * Σ_t (x_t * R_{t - w}) + Σ_{t = 0}^{T - 1} R_{t - w}^2
-            // = |x|^2 - 2 * Σ_t (x_t * R_{w - t}.flip()) + Σ_{t = -w}^{T - w - 1} R_t^2
-            // = |x|^2 - 2 * x.convolution(R.flip())_w + Σ_{t = 0}^{T - w - 1} R_t^2 - Σ_{t = 0}^{-w - 1} R_t^2
-            let convolution_at = (0 <= delay)
-                .then(|| convolution.get(delay as usize).copied())
-                .flatten()
-                .unwrap_or_default();
-            let score = squared_norm - 2 * convolution_at.as_u64()
-                + self
-                    .precalc
-                    .get(using_voice, (problem_voice.len() as isize) - delay - 1)
-                    .as_u64()
-                - self.precalc.get(using_voice, -delay - 1).as_u64();
-            if score < min_score {
-                min_score = score;
-                min_delay = delay;
+        self.evaluate_top(problem_voice, using_voice, 1)[0]
+    }
+
+    /// 損失の曲線の極小点のうち, 損失の小さい順に `k` 個を返す.
+    ///
+    /// 極小点どうしは [`Self::MIN_DELAY_SEPARATION`] 以上離れたものだけを選ぶ.
+    pub fn evaluate_top(
+        &self,
+        problem_voice: &Owned,
+        using_voice: CardVoiceIndex,
+        k: usize,
+    ) -> Vec<InspectPoint> {
+        let stats = ProblemStats::new(problem_voice);
+        self.evaluate_top_with(problem_voice, &stats, using_voice, k)
+    }
+
+    /// [`Self::evaluate_top`] を, 前計算した問題の `stats` を使って求める.
+    fn evaluate_top_with(
+        &self,
+        problem_voice: &Owned,
+        stats: &ProblemStats,
+        using_voice: CardVoiceIndex,
+        k: usize,
+    ) -> Vec<InspectPoint> {
+        let (first_delay, scores) = self.score_curve(problem_voice, stats, using_voice);
+
+        let mut minima: Vec<_> = (0..scores.len())
+            .filter(|&i| {
+                scores[i] != u64::MAX
+                    && (i == 0 || scores[i] <= scores[i - 1])
+                    && (i + 1 == scores.len() || scores[i] <= scores[i + 1])
+            })
+            .map(|i| InspectPoint {
+                using_voice,
+                delay: first_delay + i as isize,
+                score: scores[i],
+            })
+            .collect();
+        minima.sort_by_key(|point| (point.score, point.delay));
+
+        let mut top: Vec<InspectPoint> = vec![];
+        for point in minima {
+            if k <= top.len() {
+                break;
             }
+            if top
+                .iter()
+                .all(|p| Self::MIN_DELAY_SEPARATION <= p.delay.abs_diff(point.delay))
+            {
+                top.push(point);
+            }
+        }
+        if top.is_empty() {
+            // 重なりが短すぎてどの遅延も候補にならなかった
+            top.push(InspectPoint {
+                using_voice,
+                delay: 0,
+                score: u64::MAX,
+            });
         }
-        info!("({min_score}, {min_delay}) using {using_voice}");
-        InspectPoint {
-            using_voice,
-            delay: min_delay,
-            score: min_score,
+        for point in &top {
+            info!(
+                "({}, {}) using {}",
+                point.score,
+                point.delay,
+                self.library.label(using_voice)
+            );
         }
+        top
     }
 
-    pub fn find_points(&self, problem_voice: &Owned) -> Vec<InspectPoint> {
-        let mut points_by_loss: Vec<_> = CardVoiceIndex::all()
-            .map(|index| self.evaluate(problem_voice, index))
+    /// 遅延ごとの損失を求める. 返り値は, 先頭の遅延と, そこから 1 ずつ遅延を増やしたときの損失.
+    fn score_curve(
+        &self,
+        problem_voice: &Owned,
+        stats: &ProblemStats,
+        using_voice: CardVoiceIndex,
+    ) -> (isize, Vec<u64>) {
+        let flipped = &self.flipped_card_voices[&using_voice];
+        let convolution = if problem_voice.convolution_width(flipped) != Some(2) {
+            // 既定の法では畳み込みを表せないほど値が大きいので, 法を増やして求めてから剰余に戻す.
+            // 損失は剰余環の上で計算するので, 損失そのものが法に収まれば結果は変わらない.
+            problem_voice
+                .exact_convolution(flipped, &self.exact_ntts)
+                .into_iter()
+                .map(Pixel::from_i128)
+                .collect()
+        } else if !flipped.is_empty()
+            && Self::OVERLAP_SAVE_RATIO * flipped.len() < problem_voice.len()
+        {
+            let kernel = self.overlap_save_kernels[&using_voice].get_or_init(|| {
+                OverlapSaveKernel::new(flipped, Self::OVERLAP_SAVE_RATIO * flipped.len(), &self.ntt)
+            });
+            problem_voice.overlap_save_with(kernel, &self.ntt)
+        } else {
+            problem_voice.convolution(flipped, &self.ntt)
+        };
+        let card_len = self.card_voices[&using_voice].len() as isize;
+
+        // 読み札と問題が少しでも重なる遅延の範囲. どちらが長くてもよい.
+        let delays = (1 - stats.len)..card_len;
+        let scores = delays
+            .clone()
+            .map(|delay| {
+                // R' : R.flip() shifted by L - 1, so that R'_k = R_{L - 1 - k}
+                // Σ_t (x_t * R_{t + w}) = Σ_t (x_t * R'_{L - 1 - w - t}) = x.convolution(R')_{L - 1 - w}
+                let convolution_at = convolution[(card_len - 1 - delay) as usize];
+                self.score_at(using_voice, stats, delay, convolution_at)
+            })
             .collect();
-        points_by_loss.sort_unstable_by_key(|point| point.score);
-        points_by_loss
+        (delays.start, scores)
     }
 
-    pub fn validate(&self, problem_voice: &Owned, answer: &[InspectPoint]) -> bool {
-        let len = problem_voice.len();
-        let mut composed = Owned::new();
-        for &InspectPoint {
-            using_voice, delay, ..
-        } in answer
-        {
-            composed = composed
-                .add(self.card_voices[&using_voice].clone().delay(delay))
-                .to_owned(len);
+    /// `delays` の範囲だけで損失の最も小さい遅延を求める. 畳み込みを使わず, 遅延ごとに内積を計算する.
+    pub fn evaluate_window(
+        &self,
+        problem_voice: &Owned,
+        using_voice: CardVoiceIndex,
+        delays: Range<isize>,
+    ) -> InspectPoint {
+        let stats = ProblemStats::new(problem_voice);
+        self.evaluate_window_with(problem_voice, &stats, using_voice, delays)
+    }
+
+    /// [`Self::evaluate_window`] を, 前計算した問題の `stats` を使って求める.
+    fn evaluate_window_with(
+        &self,
+        problem_voice: &Owned,
+        stats: &ProblemStats,
+        using_voice: CardVoiceIndex,
+        delays: Range<isize>,
+    ) -> InspectPoint {
+        let card_voice = &self.card_voices[&using_voice];
+        let card_len = card_voice.len() as isize;
+        let delays = delays.start.max(1 - stats.len)..delays.end.min(card_len);
+        delays
+            .map(|delay| {
+                let convolution_at = ((-delay).max(0)..stats.len.min(card_len - delay))
+                    .map(|t| problem_voice.get(t) * card_voice.get(t + delay))
+                    .sum();
+                InspectPoint {
+                    using_voice,
+                    delay,
+                    score: self.score_at(using_voice, stats, delay, convolution_at),
+                }
+            })
+            .min_by_key(|point| (point.score, point.delay))
+            .unwrap_or(InspectPoint {
+                using_voice,
+                delay: 0,
+                score: u64::MAX,
+            })
+    }
+
+    /// 読み札を `delay` だけ遅らせたときの損失. `convolution_at` は問題と遅らせた読み札の内積.
+    fn score_at(
+        &self,
+        using_voice: CardVoiceIndex,
+        stats: &ProblemStats,
+        delay: isize,
+        convolution_at: Pixel,
+    ) -> u64 {
+        // R : using voice (length L)
+        // T : problem voice length
+        // x : problem voice
+        // w : how long delayed, so that R.delayed(w)_t = R_{t + w}
+        // f(w) = |x - R.delayed(w).clip()|^2
+        // = |x|^2 - 2 * x * R.delayed(w).clip() + |R.delayed(w).clip()|^2
+        // = |x|^2 - 2 * Σ_t (x_t * R_{t + w}) + Σ_{t = 0}^{T - 1} R_{t + w}^2
+        // = |x|^2 - 2 * Σ_t (x_t * R_{t + w}) + Σ_{t = 0}^{T + w - 1} R_t^2 - Σ_{t = 0}^{w - 1} R_t^2
+        let card_len = self.card_voices[&using_voice].len() as isize;
+        let card_energy = self.precalc.get(using_voice, stats.len + delay - 1)
+            - self.precalc.get(using_voice, delay - 1);
+        // 剰余環の上で計算すれば, 途中が負になっても最終的な値は非負の整数になる
+        let squared_error =
+            (stats.squared_norm - convolution_at - convolution_at + card_energy).as_u64();
+        // 問題のうち読み札と重なっている範囲は [overlap_start, overlap_end)
+        let overlap_start = (-delay).max(0);
+        let overlap_end = stats.len.min(card_len - delay);
+        let problem_energy = prefix_sum_at(&stats.prefix_sum, overlap_end - 1)
+            - prefix_sum_at(&stats.prefix_sum, overlap_start - 1);
+        self.scoring.score(
+            (overlap_end - overlap_start) as usize,
+            stats.len.min(card_len) as usize,
+            problem_energy.as_u64(),
+            card_energy.as_u64(),
+            convolution_at.as_i64(),
+            squared_error,
+        )
+    }
+
+    pub fn find_points(&self, problem_voice: &Owned) -> Vec<InspectPoint> {
+        self.find_points_top(problem_voice, 1)
+    }
+
+    /// すべての読み札について [`Self::evaluate_top`] で `k` 個ずつ候補を求め, 損失の小さい順に並べる.
+    ///
+    /// [`Self::with_coarse`] で間引いた音声を用意してあれば, 先に間引いた音声で候補を絞り込み,
+    /// 絞り込んだ候補の遅延の周りだけを元の音声で評価する.
+    pub fn find_points_top(&self, problem_voice: &Owned, k: usize) -> Vec<InspectPoint> {
+        self.find_points_until(problem_voice, k, None)
+    }
+
+    /// [`Self::find_points_top`] と同じだが, `deadline` を過ぎたら残りの読み札は評価せず, それまでの候補だけを並べる.
+    pub fn find_points_until(
+        &self,
+        problem_voice: &Owned,
+        k: usize,
+        deadline: Option<Instant>,
+    ) -> Vec<InspectPoint> {
+        let mut points_by_loss: Vec<_> = match &self.coarse {
+            Some(coarse) => coarse.find_points(self, problem_voice, k, deadline),
+            None => {
+                // 問題の前計算は読み札によらないので 1 度だけ行う
+                let stats = ProblemStats::new(problem_voice);
+                self.library
+                    .all()
+                    .take_while(|_| deadline.is_none_or(|deadline| Instant::now() < deadline))
+                    .flat_map(|index| self.evaluate_top_with(problem_voice, &stats, index, k))
+                    .collect()
+            }
+        };
+        match &self.spectrogram {
+            Some(fusion) => {
+                let problem = Spectrogram::new(problem_voice, &fusion.fft);
+                let mut fused: Vec<_> = points_by_loss
+                    .into_iter()
+                    .map(|point| (fusion.fuse(&problem, &point), point))
+                    .collect();
+                fused.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+                fused.into_iter().map(|(_, point)| point).collect()
+            }
+            None => {
+                points_by_loss.sort_by_key(|point| point.score);
+                points_by_loss
+            }
         }
+    }
 
-        let composed_norm = problem_voice
-            .clone()
-            .sub(composed.clip(len))
+    /// [`Self::compose`] で重ね合わせたものを, 問題の音声を作るときと同じように [`Mixing`] で 16 ビットの範囲に収める.
+    pub fn mix(&self, len: usize, answer: &[InspectPoint]) -> Owned {
+        self.mixing.mix(&self.compose(len, answer))
+    }
+
+    /// `answer` の読み札を遅らせて重ね合わせた, 長さ `len` の音声を作る. 範囲を超えた値もそのまま残す.
+    pub fn compose(&self, len: usize, answer: &[InspectPoint]) -> Owned {
+        let mut composed = vec![Pixel::default(); len];
+        let mut card = vec![Pixel::default(); len];
+        for point in answer {
+            self.card_voices[&point.using_voice].fill(point.delay, &mut card);
+            for (px, card) in composed.iter_mut().zip(&card) {
+                *px += *card;
+            }
+        }
+        Owned::from_pixels(composed)
+    }
+
+    /// `point` の読み札を遅らせた, 長さ `len` の音声を作る.
+    pub fn delayed_card(&self, point: &InspectPoint, len: usize) -> Owned {
+        (&self.card_voices[&point.using_voice])
+            .window(point.delay, len)
             .to_owned(len)
-            .squared_norm()
-            .as_u64()
-            / len as u64;
+    }
+
+    /// 問題の音声から `answer` を重ね合わせたものを引いた残差.
+    pub fn residual(&self, problem_voice: &Owned, answer: &[InspectPoint]) -> Owned {
+        let len = problem_voice.len();
+        problem_voice.sub(&self.mix(len, answer)).to_owned(len)
+    }
+
+    /// 解の候補の遅延を合わせ直す. 各札の遅延を, ほかの札を引いた残差に対して求め直すことを,
+    /// 遅延が変わらなくなるか `rounds` 回繰り返すまで行う.
+    pub fn refine(
+        &self,
+        problem_voice: &Owned,
+        answer: &[InspectPoint],
+        rounds: usize,
+    ) -> Vec<InspectPoint> {
+        let mut answer = answer.to_vec();
+        for _ in 0..rounds {
+            let mut changed = false;
+            for i in 0..answer.len() {
+                let others: Vec<_> = answer
+                    .iter()
+                    .enumerate()
+                    .filter(|&(j, _)| j != i)
+                    .map(|(_, &point)| point)
+                    .collect();
+                let residual = self.residual(problem_voice, &others);
+                let point = self.evaluate(&residual, answer[i].using_voice);
+                changed |= point.delay != answer[i].delay;
+                answer[i] = point;
+            }
+            if !changed {
+                break;
+            }
+        }
+        answer
+    }
+
+    /// 読み札の枚数を推定する. 損失の小さい札から順に加えてみて, 残差を
+    /// [`Self::MIN_RESIDUAL_REDUCTION`] の割合以上減らす札だけを残す.
+    /// 続けて [`Self::MAX_ESTIMATE_MISSES`] 枚が残らなければ打ち切る.
+    pub fn estimate_count(
+        &self,
+        problem_voice: &Owned,
+        points_by_loss: &[InspectPoint],
+        max: usize,
+    ) -> usize {
+        let mut answer: Vec<InspectPoint> = vec![];
+        let mut residual = self.validate(problem_voice, &answer);
+        let mut misses = 0;
+        for point in points_by_loss {
+            if max <= answer.len() || residual.is_valid() || Self::MAX_ESTIMATE_MISSES <= misses {
+                break;
+            }
+            if answer.iter().any(|p| p.using_voice == point.using_voice) {
+                continue;
+            }
+            answer.push(*point);
+            let next = self.validate(problem_voice, &answer);
+            let reduction = residual.score.saturating_sub(next.score) as f64;
+            if reduction < residual.score as f64 * Self::MIN_RESIDUAL_REDUCTION {
+                answer.pop();
+                misses += 1;
+                continue;
+            }
+            residual = next;
+            misses = 0;
+        }
+        info!("estimated count: {}", answer.len());
+        answer.len()
+    }
+
+    pub fn validate(&self, problem_voice: &Owned, answer: &[InspectPoint]) -> Validation {
+        let len = problem_voice.len();
+        let composed_norm =
+            self.residual(problem_voice, answer).squared_norm().as_u64() / len as u64;
         info!("validation : score of {answer:?} is\n\t{composed_norm:?}");
-        let threshold = 10;
-        composed_norm < threshold
+        Validation {
+            score: composed_norm,
+        }
+    }
+}
+
+/// 損失を求めるために前計算しておく, 問題の音声の量.
+struct ProblemStats {
+    len: isize,
+    squared_norm: Pixel,
+    prefix_sum: Vec<Pixel>,
+}
+
+impl ProblemStats {
+    fn new(problem_voice: &Owned) -> Self {
+        Self {
+            len: problem_voice.len() as isize,
+            squared_norm: problem_voice.squared_norm(),
+            prefix_sum: squared_prefix_sum(problem_voice),
+        }
+    }
+}
+
+/// [`Loss::validate`] による検算の結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Validation {
+    /// 残差の 1 サンプルあたりの 2 乗ノルム
+    pub score: u64,
+}
+
+impl Validation {
+    const THRESHOLD: u64 = 10;
+
+    #[inline]
+    pub fn is_valid(self) -> bool {
+        self.score < Self::THRESHOLD
     }
 }
 
 #[test]
 fn validate_e01() -> anyhow::Result<()> {
-    use crate::{load_all_jk, MockRequester, Requester};
+    use crate::{
+        precalc::load_card_voices,
+        request::{mock::MockRequester, Requester},
+    };
 
     // E01 + E02 + E03 = Q_E01
-    let loss = Loss::new(load_all_jk()?);
+    let library = CardLibrary::from_env()?;
+    let card_voices = load_card_voices(&library)?;
+    let loss = Loss::new(library, card_voices);
+    let card = |label| loss.library().find(label).unwrap();
 
     let requester = MockRequester::new(["assets", "sample", "sample_Q_E01"].into_iter().collect());
     let chunks = requester.get_chunks(1)?;
     let chunk = &chunks[0];
 
-    assert!(loss.validate(
-        chunk,
-        &[
-            InspectPoint {
-                delay: 0,
-                score: 0,
-                using_voice: CardVoiceIndex::new(0)
-            },
-            InspectPoint {
-                delay: 0,
-                score: 0,
-                using_voice: CardVoiceIndex::new(1)
-            },
-            InspectPoint {
-                delay: 0,
-                score: 0,
-                using_voice: CardVoiceIndex::new(2)
-            }
-        ]
-    ));
+    assert!(loss
+        .validate(
+            chunk,
+            &[
+                InspectPoint {
+                    delay: 0,
+                    score: 0,
+                    using_voice: card("E01")
+                },
+                InspectPoint {
+                    delay: 0,
+                    score: 0,
+                    using_voice: card("E02")
+                },
+                InspectPoint {
+                    delay: 0,
+                    score: 0,
+                    using_voice: card("E03")
+                }
+            ]
+        )
+        .is_valid());
+
+    Ok(())
+}
+
+/// 疑似乱数で作った読み札 `card_lens.len()` 枚からなる [`Loss`] を作る.
+#[cfg(test)]
+fn synthetic_loss(card_lens: &[usize]) -> Loss {
+    use self::card_voice::{Card, Language};
+
+    let library = CardLibrary::new(
+        (0..card_lens.len())
+            .map(|i| Card {
+                path: format!("{i}.wav").into(),
+                label: format!("C{i}"),
+                answer: i.to_string(),
+                language: Language::En,
+            })
+            .collect(),
+    )
+    .unwrap();
+    let mut seed = 12345u64;
+    let card_voices = library
+        .all()
+        .zip(card_lens)
+        .map(|(index, &len)| {
+            let pcm: Vec<_> = (0..len)
+                .map(|_| {
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    (seed >> 49) as i16 - 8192
+                })
+                .collect();
+            (index, Owned::from_pcm(&pcm))
+        })
+        .collect();
+    Loss::new(library, card_voices)
+}
+
+#[test]
+fn evaluate_chunks_shorter_and_longer_than_card() {
+    let loss = synthetic_loss(&[300, 1000]);
+    let short = loss.library().find("C0").unwrap();
+    let long = loss.library().find("C1").unwrap();
+
+    for (card, problem_len, delay) in [
+        // 読み札より短い問題
+        (long, 200, 0),
+        (long, 200, 500),
+        (long, 200, 999),
+        (long, 200, -150),
+        // 読み札より長い問題
+        (short, 800, -250),
+        (short, 800, 100),
+        (short, 800, -799),
+        (short, 800, 299),
+        // 読み札の OVERLAP_SAVE_RATIO 倍より長い問題
+        (short, 3000, -2000),
+        (short, 3000, 299),
+    ] {
+        let point = InspectPoint {
+            using_voice: card,
+            delay,
+            score: 0,
+        };
+        let problem = loss.delayed_card(&point, problem_len);
+        let found = loss.evaluate(&problem, card);
+        assert_eq!((found.delay, found.score), (delay, 0), "{point:?}");
+        assert!(loss.validate(&problem, &[found]).is_valid());
+    }
+}
+
+#[test]
+fn overlap_save_kernel_is_cached() {
+    let loss = synthetic_loss(&[300]);
+    let card = loss.library().find("C0").unwrap();
+    let point = InspectPoint {
+        using_voice: card,
+        delay: -2000,
+        score: 0,
+    };
+    let problem = loss.delayed_card(&point, 3000);
+    assert!(loss.overlap_save_kernels[&card].get().is_none());
+    let first = loss.evaluate(&problem, card);
+    assert!(loss.overlap_save_kernels[&card].get().is_some());
+    assert_eq!(loss.evaluate(&problem, card), first);
+    assert_eq!((first.delay, first.score), (-2000, 0));
+}
+
+#[test]
+fn normalized_scorings_rank_short_cards() {
+    // 読み札も問題も Scoring::MIN_OVERLAP より短い
+    for scoring in [Scoring::ErrorPerSample, Scoring::Correlation] {
+        let loss = synthetic_loss(&[800, 800, 800]).with_scoring(scoring);
+        let card = loss.library().find("C1").unwrap();
+        let point = InspectPoint {
+            using_voice: card,
+            delay: -100,
+            score: 0,
+        };
+        let problem = loss.delayed_card(&point, 1000);
+        let best = loss.find_points(&problem)[0];
+        assert_eq!((best.using_voice, best.delay), (card, -100), "{scoring}");
+        assert_ne!(best.score, u64::MAX, "{scoring}");
+    }
+}
+
+#[test]
+fn score_curve_widens_moduli_for_large_values() {
+    let loss = synthetic_loss(&[100_000]);
+    let card = loss.library().find("C0").unwrap();
+    let point = InspectPoint {
+        using_voice: card,
+        delay: 0,
+        score: 0,
+    };
+    // 畳み込みの上限の見積もりは既定の法を超えるが, 損失は法に収まる大きさの外れ値を加える
+    let spike = 600_000_000;
+    let delayed = loss.delayed_card(&point, 120_000);
+    let problem = Owned::from_pixels((0..120_000).map(|t| match t {
+        119_999 => delayed.get(t) + Pixel::from_signed(spike),
+        _ => delayed.get(t),
+    }));
+    let flipped = &loss.flipped_card_voices[&card];
+    assert_ne!(problem.convolution_width(flipped), Some(2));
+
+    let stats = ProblemStats::new(&problem);
+    let (first_delay, scores) = loss.score_curve(&problem, &stats, card);
+    for delay in [0, 500, -20_000, -119_000] {
+        let expected = loss.evaluate_window(&problem, card, delay..delay + 1);
+        assert_eq!(
+            scores[(delay - first_delay) as usize],
+            expected.score,
+            "{delay}"
+        );
+    }
+    assert_eq!(scores[-first_delay as usize], (spike * spike) as u64);
+}
+
+#[test]
+fn find_points_stops_at_deadline() {
+    let loss = synthetic_loss(&[300, 300, 300]);
+    let problem = loss.delayed_card(
+        &InspectPoint {
+            using_voice: loss.library().find("C1").unwrap(),
+            delay: 0,
+            score: 0,
+        },
+        300,
+    );
+    assert!(loss
+        .find_points_until(&problem, 1, Some(Instant::now()))
+        .is_empty());
+    let far = Instant::now() + std::time::Duration::from_secs(3600);
+    assert_eq!(
+        loss.find_points_until(&problem, 1, Some(far)),
+        loss.find_points(&problem)
+    );
+}
+
+#[test]
+fn refine_recovers_wrong_delays() {
+    let loss = synthetic_loss(&[1000, 1000, 1000]);
+    let truth: Vec<_> = [("C0", -100), ("C1", -900), ("C2", 300)]
+        .into_iter()
+        .map(|(label, delay)| InspectPoint {
+            using_voice: loss.library().find(label).unwrap(),
+            delay,
+            score: 0,
+        })
+        .collect();
+    let problem = loss.mix(2000, &truth);
+
+    let wrong: Vec<_> = truth
+        .iter()
+        .map(|point| InspectPoint {
+            delay: point.delay + 37,
+            ..*point
+        })
+        .collect();
+    assert!(!loss.validate(&problem, &wrong).is_valid());
+    let refined = loss.refine(&problem, &wrong, 2);
+    assert!(loss.validate(&problem, &refined).is_valid());
+    let delays: Vec<_> = refined.iter().map(|point| point.delay).collect();
+    assert_eq!(delays, [-100, -900, 300]);
+}
+
+#[test]
+fn estimate_count_of_synthetic_problem() {
+    let loss = synthetic_loss(&[800, 800, 800, 800, 800, 800]);
+    let truth: Vec<_> = [("C1", -100), ("C3", -700), ("C4", 200)]
+        .into_iter()
+        .map(|(label, delay)| InspectPoint {
+            using_voice: loss.library().find(label).unwrap(),
+            delay,
+            score: 0,
+        })
+        .collect();
+    let problem = loss.mix(1500, &truth);
+
+    let points = loss.find_points(&problem);
+    assert_eq!(loss.estimate_count(&problem, &points, 6), 3);
+    assert_eq!(loss.estimate_count(&problem, &points, 2), 2);
+}
+
+#[test]
+fn coarse_to_fine_finds_exact_delays() {
+    let loss = synthetic_loss(&[4000, 4000, 4000]).with_coarse(4);
+    let truth: Vec<_> = [("C0", -1001), ("C2", 503)]
+        .into_iter()
+        .map(|(label, delay)| InspectPoint {
+            using_voice: loss.library().find(label).unwrap(),
+            delay,
+            score: 0,
+        })
+        .collect();
+    let problem = loss.mix(6000, &truth);
+
+    let mut found: Vec<_> = loss.find_points_top(&problem, 1)[..2]
+        .iter()
+        .map(|point| (point.using_voice, point.delay))
+        .collect();
+    found.sort_unstable();
+    let expected: Vec<_> = truth
+        .iter()
+        .map(|point| (point.using_voice, point.delay))
+        .collect();
+    assert_eq!(found, expected);
+}
+
+#[test]
+fn evaluate_top_finds_repeated_card() {
+    let loss = synthetic_loss(&[1000]);
+    let card = loss.library().find("C0").unwrap();
+    let points: Vec<_> = [-200, -1800]
+        .into_iter()
+        .map(|delay| InspectPoint {
+            using_voice: card,
+            delay,
+            score: 0,
+        })
+        .collect();
+    let problem = loss.compose(3000, &points);
+
+    let mut delays: Vec<_> = loss
+        .evaluate_top(&problem, card, 2)
+        .into_iter()
+        .map(|point| point.delay)
+        .collect();
+    delays.sort_unstable();
+    assert_eq!(delays, [-1800, -200]);
+}
+
+#[test]
+fn evaluate_sample_e02() -> anyhow::Result<()> {
+    use crate::{
+        precalc::load_card_voices,
+        request::{mock::MockRequester, Requester},
+    };
+
+    // E01, E02, E03 がそれぞれ 4800, 9600, 14400 サンプル目から重ね合わされている
+    let library = CardLibrary::from_env()?;
+    let card_voices = load_card_voices(&library)?;
+    let loss = Loss::new(library, card_voices);
+
+    let requester = MockRequester::new(["assets", "sample", "sample_Q_E02"].into_iter().collect());
+    let chunks = requester.get_chunks(1)?;
+    let chunk = &chunks[0];
+
+    for (label, delay) in [("E01", 4800), ("E02", 9600), ("E03", 14400)] {
+        let point = loss.evaluate(chunk, loss.library().find(label).unwrap());
+        assert_eq!(point.delay, delay, "{label}");
+    }
 
     Ok(())
 }
@@ -0,0 +1,353 @@
+use std::{collections::HashMap, time::Instant};
+
+use log::{info, warn};
+
+use crate::audio_vec::owned::Owned;
+
+use super::{constraint::Constraints, InspectPoint, Loss};
+
+/// 解の候補から, 重ね合わせの残差が最小になる組み合わせを分枝限定法で選ぶ.
+///
+/// 残差 `|x - Σ c_i|^2` を, 問題と候補の内積と候補どうしの内積に展開して計算する.
+/// 重ね合わせの飽和は考えないので, 選んだ解は [`Loss::validate`] で検算すること.
+pub struct BranchAndBound {
+    candidates: Vec<InspectPoint>,
+    /// 候補ごとの取り札の番号. 同じ取り札の候補は 1 つしか選べない
+    groups: Vec<usize>,
+    /// 必ず含める取り札の番号
+    forced: Vec<usize>,
+    /// `-2 <x, c_j> + <c_j, c_j>`
+    linear: Vec<i64>,
+    /// `<c_i, c_j>`
+    gram: Vec<Vec<i64>>,
+    /// 異なる候補どうしの内積の最小値. 0 以下にしておく
+    min_cross: i64,
+    problem_energy: i64,
+    solutions: usize,
+    nodes: usize,
+    best: Option<(i64, Vec<usize>)>,
+    /// これを過ぎたら探索をやめる
+    deadline: Option<Instant>,
+    /// 期限が来たか, 呼び出し元に止められた
+    stopped: bool,
+}
+
+impl BranchAndBound {
+    /// 探索する節点の数の上限. 超えたらそれまでの最良の解を返す.
+    pub const MAX_NODES: usize = 1_000_000;
+
+    /// `points_by_loss` のうち損失の小さい `max_candidates` 個と, 必ず含める札の候補から探索の準備をする.
+    pub fn new(
+        loss: &Loss,
+        problem_voice: &Owned,
+        points_by_loss: &[InspectPoint],
+        constraints: &Constraints,
+        solutions: usize,
+        max_candidates: usize,
+    ) -> Self {
+        let library = loss.library();
+        let allowed: Vec<_> = points_by_loss
+            .iter()
+            .filter(|point| !constraints.exclude.contains(&point.using_voice))
+            .collect();
+        let candidates: Vec<InspectPoint> = allowed
+            .iter()
+            .enumerate()
+            .filter(|&(i, point)| {
+                i < max_candidates || constraints.include.contains(&point.using_voice)
+            })
+            .map(|(_, &&point)| point)
+            .collect();
+
+        let mut group_ids = HashMap::new();
+        let groups: Vec<_> = candidates
+            .iter()
+            .map(|point| {
+                let next = group_ids.len();
+                *group_ids
+                    .entry(library.answer(point.using_voice))
+                    .or_insert(next)
+            })
+            .collect();
+        let mut forced: Vec<_> = constraints
+            .include
+            .iter()
+            .filter_map(|&index| group_ids.get(library.answer(index)).copied())
+            .collect();
+        forced.sort_unstable();
+        forced.dedup();
+
+        let len = problem_voice.len();
+        let problem = problem_voice.to_pcm();
+        // 候補ごとに, 問題の範囲で読み札が鳴っている区間とその波形
+        let voices: Vec<_> = candidates
+            .iter()
+            .map(|point| {
+                let card_len = loss.card_voices[&point.using_voice].len() as isize;
+                let start = (-point.delay).clamp(0, len as isize) as usize;
+                let end = (card_len - point.delay).clamp(0, len as isize) as usize;
+                let pcm = loss.delayed_card(point, len).to_pcm();
+                (start, end.max(start), pcm)
+            })
+            .collect();
+        let dot = |a: &(usize, usize, Vec<i16>), b: &[i16], start: usize, end: usize| -> i64 {
+            (start..end).map(|t| a.2[t] as i64 * b[t] as i64).sum()
+        };
+
+        let n = candidates.len();
+        let mut gram = vec![vec![0; n]; n];
+        let mut min_cross = 0;
+        for i in 0..n {
+            for j in i..n {
+                let start = voices[i].0.max(voices[j].0);
+                let end = voices[i].1.min(voices[j].1);
+                let value = dot(&voices[i], &voices[j].2, start, end.max(start));
+                gram[i][j] = value;
+                gram[j][i] = value;
+                if i != j && groups[i] != groups[j] {
+                    min_cross = min_cross.min(value);
+                }
+            }
+        }
+        let linear = (0..n)
+            .map(|j| -2 * dot(&voices[j], &problem, voices[j].0, voices[j].1) + gram[j][j])
+            .collect();
+        let problem_energy = problem.iter().map(|&x| x as i64 * x as i64).sum();
+
+        Self {
+            candidates,
+            groups,
+            forced,
+            linear,
+            gram,
+            min_cross,
+            problem_energy,
+            solutions,
+            nodes: 0,
+            best: None,
+            deadline: None,
+            stopped: false,
+        }
+    }
+
+    /// 制約を満たし残差が最小になる `solutions` 枚の組み合わせを返す. 満たすものが無ければ `None`.
+    pub fn search(self) -> Option<Vec<InspectPoint>> {
+        self.search_until(None, |_| false)
+    }
+
+    /// [`Self::search`] を `deadline` まで行い, それまでに見つけた最良の組み合わせを返す.
+    ///
+    /// よりよい組み合わせを見つけるたびに `improved` を呼ぶ. `improved` が `true` を返したら, そこで打ち切る.
+    pub fn search_until(
+        mut self,
+        deadline: Option<Instant>,
+        mut improved: impl FnMut(&[InspectPoint]) -> bool,
+    ) -> Option<Vec<InspectPoint>> {
+        self.deadline = deadline;
+        let mut chosen = vec![];
+        self.branch(0, &mut chosen, self.problem_energy, &mut improved);
+        if Self::MAX_NODES <= self.nodes {
+            warn!("branch and bound gave up after {} nodes", self.nodes);
+        } else if self.stopped {
+            warn!("branch and bound stopped after {} nodes", self.nodes);
+        }
+        let (residual, best) = self.best.take()?;
+        info!(
+            "branch and bound: residual {residual} after {} nodes",
+            self.nodes
+        );
+        Some(self.points(&best))
+    }
+
+    fn points(&self, chosen: &[usize]) -> Vec<InspectPoint> {
+        chosen.iter().map(|&i| self.candidates[i]).collect()
+    }
+
+    /// `chosen` に候補 `j` を加えたときの残差の増分.
+    fn gain(&self, chosen: &[usize], j: usize) -> i64 {
+        self.linear[j] + 2 * chosen.iter().map(|&i| self.gram[i][j]).sum::<i64>()
+    }
+
+    fn branch(
+        &mut self,
+        start: usize,
+        chosen: &mut Vec<usize>,
+        value: i64,
+        improved: &mut impl FnMut(&[InspectPoint]) -> bool,
+    ) {
+        if self.stopped {
+            return;
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| deadline <= Instant::now())
+        {
+            self.stopped = true;
+            return;
+        }
+        self.nodes += 1;
+        if chosen.len() == self.solutions {
+            let satisfied = self
+                .forced
+                .iter()
+                .all(|group| chosen.iter().any(|&i| self.groups[i] == *group));
+            if satisfied && self.best.as_ref().is_none_or(|(best, _)| value < *best) {
+                self.best = Some((value, chosen.clone()));
+                self.stopped = improved(&self.points(chosen));
+            }
+            return;
+        }
+        if Self::MAX_NODES <= self.nodes {
+            return;
+        }
+        let remaining = self.solutions - chosen.len();
+        let missing_forced = self
+            .forced
+            .iter()
+            .filter(|&&group| chosen.iter().all(|&i| self.groups[i] != group))
+            .count();
+        if remaining < missing_forced {
+            return;
+        }
+
+        let gains: Vec<_> = (start..self.candidates.len())
+            .filter(|&j| chosen.iter().all(|&i| self.groups[i] != self.groups[j]))
+            .map(|j| (self.gain(chosen, j), j))
+            .collect();
+        if gains.len() < remaining {
+            return;
+        }
+        // 増分の小さいものから選び, 候補どうしの内積は最小値で見積もる
+        let mut sorted: Vec<_> = gains.iter().map(|&(gain, _)| gain).collect();
+        sorted.sort_unstable();
+        let pairs = (remaining * (remaining - 1)) as i64;
+        let bound = value + sorted[..remaining].iter().sum::<i64>() + pairs * self.min_cross;
+        if self
+            .best
+            .as_ref()
+            .is_some_and(|(best, _)| *best <= bound.max(0))
+        {
+            return;
+        }
+
+        for (gain, j) in gains {
+            chosen.push(j);
+            self.branch(j + 1, chosen, value + gain, improved);
+            chosen.pop();
+        }
+    }
+}
+
+#[test]
+fn branch_and_bound_finds_synthetic_answer() {
+    use super::synthetic_loss;
+
+    let loss = synthetic_loss(&[800, 800, 800, 800, 800, 800]);
+    let card = |label| loss.library().find(label).unwrap();
+    let truth: Vec<_> = [("C1", -100), ("C3", -700), ("C4", 200)]
+        .into_iter()
+        .map(|(label, delay)| InspectPoint {
+            using_voice: card(label),
+            delay,
+            score: 0,
+        })
+        .collect();
+    let problem = loss.mix(1500, &truth);
+    let points = loss.find_points_top(&problem, 3);
+
+    let found = BranchAndBound::new(&loss, &problem, &points, &Constraints::default(), 3, 12)
+        .search()
+        .unwrap();
+    let mut found: Vec<_> = found
+        .into_iter()
+        .map(|point| (point.using_voice, point.delay))
+        .collect();
+    found.sort_unstable();
+    assert_eq!(
+        found,
+        [(card("C1"), -100), (card("C3"), -700), (card("C4"), 200)]
+    );
+    let mut constraints = Constraints::default();
+    constraints.include.insert(card("C0"));
+    let forced = BranchAndBound::new(&loss, &problem, &points, &constraints, 3, 12)
+        .search()
+        .unwrap();
+    assert!(forced.iter().any(|point| point.using_voice == card("C0")));
+}
+
+#[test]
+fn branch_and_bound_stops_at_deadline() {
+    use super::synthetic_loss;
+
+    let loss = synthetic_loss(&[800, 800, 800, 800]);
+    let truth = [InspectPoint {
+        using_voice: loss.library().find("C2").unwrap(),
+        delay: -100,
+        score: 0,
+    }];
+    let problem = loss.mix(1000, &truth);
+    let points = loss.find_points(&problem);
+    let search = || BranchAndBound::new(&loss, &problem, &points, &Constraints::default(), 2, 4);
+
+    // 期限を過ぎていれば何も探さない
+    let mut calls = 0;
+    let found = search().search_until(Some(Instant::now()), |_| {
+        calls += 1;
+        false
+    });
+    assert_eq!((found, calls), (None, 0));
+    // 期限が先なら最後まで探す
+    let far = Instant::now() + std::time::Duration::from_secs(3600);
+    assert_eq!(
+        search().search_until(Some(far), |_| false),
+        search().search()
+    );
+}
+
+#[test]
+fn branch_and_bound_reports_improvements() {
+    use super::synthetic_loss;
+
+    let loss = synthetic_loss(&[800, 800, 800, 800, 800, 800]);
+    let truth: Vec<_> = [("C1", -100), ("C3", -700), ("C4", 200)]
+        .into_iter()
+        .map(|(label, delay)| InspectPoint {
+            using_voice: loss.library().find(label).unwrap(),
+            delay,
+            score: 0,
+        })
+        .collect();
+    let problem = loss.mix(1500, &truth);
+    // 損失の大きい候補から探させて, よりよい組み合わせが順に見つかるようにする
+    let mut points = loss.find_points_top(&problem, 3);
+    points.reverse();
+    let search = || {
+        BranchAndBound::new(
+            &loss,
+            &problem,
+            &points,
+            &Constraints::default(),
+            3,
+            points.len(),
+        )
+    };
+
+    // 最後に見つけたものが最良
+    let mut improvements = vec![];
+    let found = search().search_until(None, |answer| {
+        improvements.push(loss.validate(&problem, answer));
+        false
+    });
+    assert!(improvements.len() >= 2, "{improvements:?}");
+    assert!(improvements.last().unwrap().is_valid());
+    assert_eq!(found, search().search());
+
+    // 打ち切れば, それまでに見つけたものを返す
+    let mut first = None;
+    let stopped = search().search_until(None, |answer| {
+        first = Some(answer.to_vec());
+        true
+    });
+    assert_eq!(stopped, first);
+    assert!(!loss.validate(&problem, &stopped.unwrap()).is_valid());
+}
@@ -1,34 +1,151 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+/// [`CardLibrary`] に登録された読み札の番号.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct CardVoiceIndex(u8);
 
-impl std::fmt::Display for CardVoiceIndex {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if self.0 < 44 {
-            write!(f, "E{:02}", self.0 + 1)
-        } else {
-            write!(f, "J{:02}", self.0 + 1 - 44)
-        }
+impl CardVoiceIndex {
+    #[inline]
+    pub fn as_usize(self) -> usize {
+        self.0 as usize
     }
 }
 
-impl CardVoiceIndex {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Language {
+    En,
+    Ja,
+}
+
+/// 読み札 1 枚分の情報.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Card {
+    /// 音声ファイルの場所. manifest からの相対パスは読み込み時に解決される.
+    pub path: PathBuf,
+    /// ログなどで表示する名前.
+    pub label: String,
+    /// 解答として送信する札の番号. 同じ番号の札は同じ絵札を表す.
+    pub answer: String,
+    pub language: Language,
+}
+
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    cards: Vec<Card>,
+}
+
+#[derive(Debug, Error)]
+pub enum LibraryError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid manifest: {0}")]
+    Manifest(#[from] serde_yaml::Error),
+    #[error("no cards in manifest")]
+    Empty,
+    #[error("too many cards in manifest: {0}")]
+    TooMany(usize),
+    #[error("duplicated label: {0}")]
+    DuplicatedLabel(String),
+}
+
+/// 読み札の一覧. manifest から読み込まれ, [`CardVoiceIndex`] はこの一覧の中の位置を表す.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CardLibrary {
+    cards: Vec<Card>,
+}
+
+impl CardLibrary {
+    /// 環境変数 `CARD_MANIFEST` が無いときに使う manifest の場所.
+    pub const DEFAULT_MANIFEST: &'static str = "assets/jk/manifest.yaml";
+
+    pub fn load(manifest_path: &Path) -> Result<Self, LibraryError> {
+        let manifest: Manifest = serde_yaml::from_reader(File::open(manifest_path)?)?;
+        let base = manifest_path.parent().unwrap_or_else(|| Path::new(""));
+        let cards = manifest
+            .cards
+            .into_iter()
+            .map(|card| Card {
+                path: base.join(&card.path),
+                ..card
+            })
+            .collect();
+        Self::new(cards)
+    }
+
+    /// 環境変数 `CARD_MANIFEST` の manifest を, 無ければ [`Self::DEFAULT_MANIFEST`] を読み込む.
+    pub fn from_env() -> Result<Self, LibraryError> {
+        let path = std::env::var("CARD_MANIFEST").unwrap_or_else(|_| Self::DEFAULT_MANIFEST.into());
+        Self::load(Path::new(&path))
+    }
+
+    pub fn new(cards: Vec<Card>) -> Result<Self, LibraryError> {
+        if cards.is_empty() {
+            return Err(LibraryError::Empty);
+        }
+        if u8::MAX as usize + 1 < cards.len() {
+            return Err(LibraryError::TooMany(cards.len()));
+        }
+        let mut labels = HashSet::new();
+        for card in &cards {
+            if !labels.insert(&card.label) {
+                return Err(LibraryError::DuplicatedLabel(card.label.clone()));
+            }
+        }
+        Ok(Self { cards })
+    }
+
     #[inline]
-    pub fn new(index: u8) -> Self {
-        assert!(index < 88);
-        Self(index)
+    pub fn all(&self) -> impl Iterator<Item = CardVoiceIndex> {
+        (0..self.cards.len()).map(|index| CardVoiceIndex(index as u8))
     }
 
     #[inline]
-    pub fn all() -> impl Iterator<Item = CardVoiceIndex> {
-        (0..88).map(Self::new)
+    pub fn card(&self, index: CardVoiceIndex) -> &Card {
+        &self.cards[index.as_usize()]
     }
 
     #[inline]
-    pub fn into_answer_string(self) -> String {
-        if self.0 < 44 {
-            (self.0 + 1).to_string()
-        } else {
-            (self.0 + 1 - 44).to_string()
-        }
+    pub fn label(&self, index: CardVoiceIndex) -> &str {
+        &self.card(index).label
+    }
+
+    #[inline]
+    pub fn answer(&self, index: CardVoiceIndex) -> &str {
+        &self.card(index).answer
+    }
+
+    /// ラベルから読み札を探す.
+    pub fn find(&self, label: &str) -> Option<CardVoiceIndex> {
+        self.cards
+            .iter()
+            .position(|card| card.label == label)
+            .map(|index| CardVoiceIndex(index as u8))
+    }
+}
+
+#[test]
+fn load_default_manifest() -> Result<(), LibraryError> {
+    let library = CardLibrary::load(Path::new(CardLibrary::DEFAULT_MANIFEST))?;
+    assert_eq!(library.all().count(), 88);
+    for index in library.all() {
+        assert_eq!(library.find(library.label(index)), Some(index));
     }
+    let e01 = library.find("E01").unwrap();
+    let j01 = library.find("J01").unwrap();
+    assert_eq!(library.answer(e01), library.answer(j01));
+    assert_eq!(library.card(j01).language, Language::Ja);
+    assert_eq!(
+        library.card(e01).path,
+        Path::new("assets/jk/E01.wav").to_path_buf()
+    );
+    assert_eq!(library.find("E45"), None);
+    Ok(())
 }
@@ -0,0 +1,188 @@
+use std::collections::BTreeSet;
+
+use log::warn;
+use thiserror::Error;
+
+use super::{
+    card_voice::{CardLibrary, CardVoiceIndex},
+    InspectPoint,
+};
+
+/// 操作者が解答に課す制約.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Constraints {
+    /// 必ず解答に含める読み札
+    pub include: BTreeSet<CardVoiceIndex>,
+    /// 解答に含めない読み札
+    pub exclude: BTreeSet<CardVoiceIndex>,
+    /// 解答の枚数. `None` なら問題の `data` に従う
+    pub count: Option<usize>,
+}
+
+/// 制約を変更する命令. 1 行に 1 つずつ与える.
+///
+/// - `+E01`: E01 を必ず含める
+/// - `-E01`: E01 を含めない
+/// - `=4`: 解答の枚数を 4 にする
+/// - `reset`: すべての制約を取り消す
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Include(CardVoiceIndex),
+    Exclude(CardVoiceIndex),
+    Count(usize),
+    Reset,
+}
+
+#[derive(Debug, Error)]
+pub enum ParseCommandError {
+    #[error("unknown card: {0}")]
+    UnknownCard(String),
+    #[error("invalid count: {0}")]
+    InvalidCount(String),
+    #[error("unknown command: {0}")]
+    Unknown(String),
+}
+
+impl Command {
+    /// 1 行を読む. 空行と `#` から始まる行は `None` になる.
+    pub fn parse(library: &CardLibrary, line: &str) -> Result<Option<Self>, ParseCommandError> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+        if line == "reset" {
+            return Ok(Some(Command::Reset));
+        }
+        let card = |label: &str| {
+            library
+                .find(label.trim())
+                .ok_or_else(|| ParseCommandError::UnknownCard(label.to_owned()))
+        };
+        let command = if let Some(label) = line.strip_prefix('+') {
+            Command::Include(card(label)?)
+        } else if let Some(label) = line.strip_prefix('-') {
+            Command::Exclude(card(label)?)
+        } else if let Some(count) = line.strip_prefix('=') {
+            Command::Count(
+                count
+                    .trim()
+                    .parse()
+                    .map_err(|_| ParseCommandError::InvalidCount(count.to_owned()))?,
+            )
+        } else {
+            return Err(ParseCommandError::Unknown(line.to_owned()));
+        };
+        Ok(Some(command))
+    }
+}
+
+impl Constraints {
+    pub fn apply(&mut self, command: Command) {
+        match command {
+            Command::Include(index) => {
+                self.exclude.remove(&index);
+                self.include.insert(index);
+            }
+            Command::Exclude(index) => {
+                self.include.remove(&index);
+                self.exclude.insert(index);
+            }
+            Command::Count(count) => self.count = Some(count),
+            Command::Reset => *self = Self::default(),
+        }
+    }
+
+    /// 解答の枚数. 制約が無ければ `data` を使う.
+    pub fn solutions(&self, data: usize) -> usize {
+        self.count.unwrap_or(data)
+    }
+
+    /// 制約を満たす最初の解を作る. 必ず含める札を先頭に置き, 残りを損失の小さい順に埋める.
+    ///
+    /// 同じ札の候補が遅延違いで複数あるときは, 最初に現れたものを使う.
+    /// 返り値は, 最初の解と, 入れ替えに使える残りの候補.
+    pub fn first_answer(
+        &self,
+        points_by_loss: &[InspectPoint],
+        solutions: usize,
+    ) -> (Vec<InspectPoint>, Vec<InspectPoint>) {
+        let mut used = BTreeSet::new();
+        let (mut answer, others): (Vec<InspectPoint>, Vec<_>) = points_by_loss
+            .iter()
+            .filter(|point| !self.exclude.contains(&point.using_voice))
+            .partition(|point| {
+                self.include.contains(&point.using_voice) && used.insert(point.using_voice)
+            });
+        if solutions < answer.len() {
+            warn!(
+                "{} cards are forced but the answer has only {solutions}",
+                answer.len()
+            );
+        }
+        let mut rest = vec![];
+        for point in others {
+            if answer.len() < solutions && used.insert(point.using_voice) {
+                answer.push(point);
+            } else {
+                rest.push(point);
+            }
+        }
+        (answer, rest)
+    }
+
+    /// 入れ替えてよい解の位置か.
+    #[inline]
+    pub fn is_swappable(&self, point: &InspectPoint) -> bool {
+        !self.include.contains(&point.using_voice)
+    }
+}
+
+#[test]
+fn first_answer_with_constraints() {
+    use super::card_voice::{Card, Language};
+
+    let library = CardLibrary::new(
+        ["A", "B", "C", "D"]
+            .into_iter()
+            .map(|label| Card {
+                path: label.into(),
+                label: label.into(),
+                answer: label.into(),
+                language: Language::En,
+            })
+            .collect(),
+    )
+    .unwrap();
+    let card = |label| library.find(label).unwrap();
+    let points: Vec<_> = ["A", "B", "C", "D"]
+        .into_iter()
+        .enumerate()
+        .map(|(score, label)| InspectPoint {
+            using_voice: card(label),
+            delay: 0,
+            score: score as u64,
+        })
+        .collect();
+
+    let mut constraints = Constraints::default();
+    for line in ["# comment", "-B", "+D", "=3", ""] {
+        if let Some(command) = Command::parse(&library, line).unwrap() {
+            constraints.apply(command);
+        }
+    }
+    assert!(Command::parse(&library, "+Z").is_err());
+
+    let solutions = constraints.solutions(2);
+    assert_eq!(solutions, 3);
+    let (answer, rest) = constraints.first_answer(&points, solutions);
+    let labels: Vec<_> = answer
+        .iter()
+        .map(|point| library.label(point.using_voice))
+        .collect();
+    assert_eq!(labels, ["D", "A", "C"]);
+    assert!(rest.is_empty());
+    assert!(!constraints.is_swappable(&answer[0]));
+
+    constraints.apply(Command::Reset);
+    assert_eq!(constraints, Constraints::default());
+}
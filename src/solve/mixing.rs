@@ -0,0 +1,99 @@
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::audio_vec::{
+    owned::{pixel::Pixel, Owned},
+    AudioVec,
+};
+
+/// 読み札を重ね合わせて問題の音声を作るときに, 16 ビットの範囲を超えた値をどう扱うか.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Mixing {
+    /// 範囲の端に収める.
+    #[default]
+    Saturating,
+    /// 符号付き 16 ビット整数として桁あふれさせる.
+    Wrapping,
+    /// 範囲を気にせずそのまま足す.
+    Unbounded,
+}
+
+impl Mixing {
+    pub const ALL: [Mixing; 3] = [Mixing::Saturating, Mixing::Wrapping, Mixing::Unbounded];
+
+    /// 環境変数 `MIXING` から読む. 無ければ [`Mixing::Saturating`].
+    pub fn from_env() -> Result<Self, ParseMixingError> {
+        std::env::var("MIXING").map_or(Ok(Self::default()), |s| s.parse())
+    }
+
+    /// 足し合わせた 1 サンプルに適用する.
+    pub fn apply(self, px: Pixel) -> Pixel {
+        match self {
+            Mixing::Saturating => px.clamp(i16::MIN as i64, i16::MAX as i64),
+            Mixing::Wrapping => px.wrap_i16(),
+            Mixing::Unbounded => px,
+        }
+    }
+
+    /// 足し合わせた音声に適用する.
+    pub fn mix(self, sum: &Owned) -> Owned {
+        sum.map(|px| self.apply(px)).to_owned(sum.len())
+    }
+}
+
+impl std::fmt::Display for Mixing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Mixing::Saturating => "saturating",
+            Mixing::Wrapping => "wrapping",
+            Mixing::Unbounded => "none",
+        })
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("unknown mixing: {0}")]
+pub struct ParseMixingError(String);
+
+impl FromStr for Mixing {
+    type Err = ParseMixingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|mixing| mixing.to_string() == s.trim())
+            .ok_or_else(|| ParseMixingError(s.to_owned()))
+    }
+}
+
+#[test]
+fn validate_with_matching_mixing() {
+    use super::{synthetic_loss, InspectPoint};
+
+    // 3 枚とも大きな音で重なるので, 16 ビットの範囲をたびたび超える
+    let loss = synthetic_loss(&[2000, 2000, 2000]);
+    let truth: Vec<_> = ["C0", "C1", "C2"]
+        .into_iter()
+        .map(|label| InspectPoint {
+            using_voice: loss.library().find(label).unwrap(),
+            delay: 0,
+            score: 0,
+        })
+        .collect();
+    let sum = loss.compose(2000, &truth);
+    assert!(sum.to_pcm().contains(&i16::MAX));
+
+    for problem_mixing in Mixing::ALL {
+        let problem = problem_mixing.mix(&sum);
+        for mixing in Mixing::ALL {
+            let loss = synthetic_loss(&[2000, 2000, 2000]).with_mixing(mixing);
+            let validation = loss.validate(&problem, &truth);
+            assert_eq!(
+                validation.is_valid(),
+                mixing == problem_mixing,
+                "problem {problem_mixing}, validate {mixing}: {validation:?}"
+            );
+        }
+    }
+}
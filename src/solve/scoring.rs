@@ -0,0 +1,131 @@
+use std::str::FromStr;
+
+use thiserror::Error;
+
+/// [`Loss::evaluate`](super::Loss::evaluate) で遅延ごとの良さを測る方法.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Scoring {
+    /// 問題全体での 2 乗誤差. 重なりの少ない読み札ほど誤差が小さくなりやすい.
+    #[default]
+    SquaredError,
+    /// 重なっている部分での 1 サンプルあたりの 2 乗誤差.
+    ErrorPerSample,
+    /// 重なっている部分での正規化相互相関. `(1 - 相関) × CORRELATION_SCALE` を損失とする.
+    Correlation,
+}
+
+impl Scoring {
+    pub const ALL: [Scoring; 3] = [
+        Scoring::SquaredError,
+        Scoring::ErrorPerSample,
+        Scoring::Correlation,
+    ];
+
+    /// 正規化した損失で, これより短い重なりの遅延は候補にしない. 0.1 秒分.
+    /// 読み札か問題がこれより短ければ, 重なりうる最長の長さより短い遅延を候補にしない.
+    pub const MIN_OVERLAP: usize = 4800;
+
+    pub const CORRELATION_SCALE: f64 = 1e9;
+
+    /// 環境変数 `SCORING` から読む. 無ければ [`Scoring::SquaredError`].
+    pub fn from_env() -> Result<Self, ParseScoringError> {
+        std::env::var("SCORING").map_or(Ok(Self::default()), |s| s.parse())
+    }
+
+    /// 重なっている部分の長さ `overlap`, その範囲での問題と読み札のエネルギー, 相互相関, 問題全体での 2 乗誤差から損失を求める.
+    /// `max_overlap` は読み札と問題の短い方の長さで, 重なりうる最長の長さ.
+    pub fn score(
+        self,
+        overlap: usize,
+        max_overlap: usize,
+        problem_energy: u64,
+        card_energy: u64,
+        cross: i64,
+        squared_error: u64,
+    ) -> u64 {
+        if self != Scoring::SquaredError && overlap < Self::MIN_OVERLAP.min(max_overlap) {
+            return u64::MAX;
+        }
+        match self {
+            Scoring::SquaredError => squared_error,
+            Scoring::ErrorPerSample => {
+                // 重なっていない部分の誤差は除く
+                let error = problem_energy as i64 + card_energy as i64 - 2 * cross;
+                error.max(0) as u64 / overlap as u64
+            }
+            Scoring::Correlation => {
+                let denominator = (problem_energy as f64 * card_energy as f64).sqrt();
+                let correlation = if denominator == 0.0 {
+                    0.0
+                } else {
+                    cross as f64 / denominator
+                };
+                ((1.0 - correlation) * Self::CORRELATION_SCALE) as u64
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Scoring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Scoring::SquaredError => "squared_error",
+            Scoring::ErrorPerSample => "error_per_sample",
+            Scoring::Correlation => "correlation",
+        })
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("unknown scoring: {0}")]
+pub struct ParseScoringError(String);
+
+impl FromStr for Scoring {
+    type Err = ParseScoringError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|scoring| scoring.to_string() == s.trim())
+            .ok_or_else(|| ParseScoringError(s.to_owned()))
+    }
+}
+
+#[test]
+fn squared_error_ignores_overlap() {
+    for overlap in [1, Scoring::MIN_OVERLAP] {
+        assert_eq!(
+            Scoring::SquaredError.score(overlap, 48000, 9, 4, 1, 123),
+            123
+        );
+    }
+}
+
+#[test]
+fn error_per_sample_scores_overlap() {
+    let scoring = Scoring::ErrorPerSample;
+    let overlap = 2 * Scoring::MIN_OVERLAP;
+    // 重なっている部分の誤差は 1000 + 600 - 2 * 500 = 600
+    assert_eq!(
+        scoring.score(overlap, 48000, 1000, 600, 500, 7),
+        600 / overlap as u64
+    );
+    assert_eq!(scoring.score(overlap, 48000, 1000, 1000, 1000, 7), 0);
+    // 短い重なりは候補にしないが, 読み札がそれより短ければ読み札の長さまで許す
+    assert_eq!(scoring.score(100, 48000, 1000, 1000, 1000, 7), u64::MAX);
+    assert_eq!(scoring.score(100, 100, 1000, 1000, 1000, 7), 0);
+    assert_eq!(scoring.score(99, 100, 1000, 1000, 1000, 7), u64::MAX);
+}
+
+#[test]
+fn correlation_scores_overlap() {
+    let scoring = Scoring::Correlation;
+    let overlap = Scoring::MIN_OVERLAP;
+    let scale = Scoring::CORRELATION_SCALE as u64;
+    assert_eq!(scoring.score(overlap, 48000, 400, 100, 200, 7), 0);
+    assert_eq!(scoring.score(overlap, 48000, 400, 100, 0, 7), scale);
+    assert_eq!(scoring.score(overlap, 48000, 400, 100, -200, 7), 2 * scale);
+    assert_eq!(scoring.score(overlap, 48000, 0, 100, 0, 7), scale);
+    assert_eq!(scoring.score(100, 48000, 400, 100, 200, 7), u64::MAX);
+    assert_eq!(scoring.score(100, 100, 400, 100, 200, 7), 0);
+}
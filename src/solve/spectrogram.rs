@@ -0,0 +1,91 @@
+use std::f64::consts::TAU;
+
+use num::complex::Complex64;
+
+use crate::audio_vec::owned::{fft::Fft, Owned};
+
+/// 短時間フーリエ変換の振幅. 読み札の時間方向の誤差とは別の手がかりとして使う.
+#[derive(Debug, Clone)]
+pub struct Spectrogram {
+    /// `frames[f][k]` は `f * HOP` サンプル目からの窓の, 周波数 `k` の振幅
+    frames: Vec<Vec<f32>>,
+}
+
+impl Spectrogram {
+    /// 窓の長さ. 48 kHz で約 21 ミリ秒.
+    pub const FRAME_LEN: usize = 1024;
+    pub const FRAME_LEVEL: usize = Self::FRAME_LEN.trailing_zeros() as usize;
+    /// 窓をずらす幅.
+    pub const HOP: usize = 256;
+
+    /// ハン窓をかけて `HOP` ずつずらしながら変換する.
+    pub fn new(voice: &Owned, fft: &Fft) -> Self {
+        let pcm = voice.to_pcm();
+        let window: Vec<_> = (0..Self::FRAME_LEN)
+            .map(|i| 0.5 - 0.5 * (TAU * i as f64 / Self::FRAME_LEN as f64).cos())
+            .collect();
+        let mut buf = vec![Complex64::default(); Self::FRAME_LEN];
+        let frames = (0..pcm.len().div_ceil(Self::HOP))
+            .map(|f| {
+                let start = f * Self::HOP;
+                for (i, elem) in buf.iter_mut().enumerate() {
+                    let sample = pcm.get(start + i).copied().unwrap_or(0);
+                    *elem = Complex64::new(sample as f64 * window[i], 0.0);
+                }
+                fft.transform(&mut buf);
+                buf[..=Self::FRAME_LEN / 2]
+                    .iter()
+                    .map(|c| c.norm() as f32)
+                    .collect()
+            })
+            .collect();
+        Self { frames }
+    }
+
+    /// 問題の振幅 `self` に, 読み札 `card` を `delay` だけ遅らせて重ねたときの食い違い.
+    ///
+    /// 読み札の振幅のうち問題の振幅を超える分の 2 乗和を, 読み札の振幅の 2 乗和で割ったもの.
+    /// ほかの札が重なっていても増えないので, 重ね合わせに含まれる札ほど 0 に近く, 0 以上 1 以下になる.
+    pub fn match_score(&self, card: &Spectrogram, delay: isize) -> f64 {
+        // 問題の f 番目の窓は, 読み札の f + shift 番目の窓に当たる
+        let shift = (delay as f64 / Self::HOP as f64).round() as isize;
+        let first = (-shift).max(0) as usize;
+        let last = (card.frames.len() as isize - shift).min(self.frames.len() as isize);
+        let mut excess = 0.0;
+        let mut total = 0.0;
+        for f in first..last.max(first as isize) as usize {
+            let problem = &self.frames[f];
+            let card = &card.frames[(f as isize + shift) as usize];
+            for (&p, &c) in problem.iter().zip(card) {
+                let over = (c - p).max(0.0) as f64;
+                excess += over * over;
+                total += c as f64 * c as f64;
+            }
+        }
+        if total == 0.0 {
+            return 1.0;
+        }
+        excess / total
+    }
+}
+
+#[test]
+fn match_score_at_true_delay() {
+    use super::{synthetic_loss, InspectPoint};
+
+    let loss = synthetic_loss(&[4000, 4000]);
+    let card = |label| loss.library().find(label).unwrap();
+    let points = [("C0", -1024), ("C1", 512)].map(|(label, delay)| InspectPoint {
+        using_voice: card(label),
+        delay,
+        score: 0,
+    });
+    let problem = loss.mix(6000, &points);
+
+    let fft = Fft::new(Spectrogram::FRAME_LEVEL);
+    let problem = Spectrogram::new(&problem, &fft);
+    let card = Spectrogram::new(&loss.card_voices[&card("C0")], &fft);
+    let matched = problem.match_score(&card, -1024);
+    assert!(matched < 0.05, "{matched}");
+    assert!(matched * 4.0 < problem.match_score(&card, 0));
+}